@@ -0,0 +1,106 @@
+// Draws chunk meshes with the block textures stored in a 2D texture array. Every vertex carries
+// the layer of its face's texture, and the texture coordinates are worked out from the position
+// on the face, so a texture repeats once per block however large the quad is. The vertex colour
+// holds the sky light in red, the block light in green and the ambient occlusion in blue, which
+// darken the face. The sky light is dimmed further by the daylight, which is lower at night.
+// Pixels less opaque than the alpha cutoff are left out, the rest keep their alpha, which only
+// matters for materials that blend.
+
+#import bevy_pbr::mesh_functions::{get_model_matrix, mesh_position_local_to_clip}
+
+@group(1) @binding(0) var block_textures: texture_2d_array<f32>;
+@group(1) @binding(1) var block_sampler: sampler;
+struct ArrayTextureMaterial {
+    daylight: f32,
+    alpha_cutoff: f32,
+};
+
+@group(1) @binding(2) var<uniform> material: ArrayTextureMaterial;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) layer: u32,
+    @location(3) light: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) light: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let model = get_model_matrix(vertex.instance_index);
+    out.clip_position = mesh_position_local_to_clip(model, vec4<f32>(vertex.position, 1.0));
+    out.local_position = vertex.position;
+    out.normal = vertex.normal;
+    out.layer = vertex.layer;
+    out.light = vertex.light;
+    return out;
+}
+
+// the texture coordinates of a point on a face, in blocks. They run the same way as the UVs the
+// chunk mesher gives each face: along x or z, and downwards on the sides of blocks
+fn face_uv(position: vec3<f32>, normal: vec3<f32>) -> vec2<f32> {
+    if normal.y > 0.5 {
+        return position.xz;
+    }
+    if normal.y < -0.5 {
+        return vec2<f32>(position.x, -position.z);
+    }
+    if normal.x > 0.5 {
+        return vec2<f32>(-position.z, -position.y);
+    }
+    if normal.x < -0.5 {
+        return vec2<f32>(position.z, -position.y);
+    }
+    if normal.z > 0.5 {
+        return vec2<f32>(position.x, -position.y);
+    }
+    return vec2<f32>(-position.x, -position.y);
+}
+
+// a fixed brightness per face direction, so the edges between faces stay visible
+fn face_shade(normal: vec3<f32>) -> f32 {
+    if normal.y > 0.5 {
+        return 1.0;
+    }
+    if normal.y < -0.5 {
+        return 0.5;
+    }
+    if abs(normal.x) > 0.5 {
+        return 0.8;
+    }
+    return 0.65;
+}
+
+// how bright a block lit at `level`, scaled to 0..1, is. Every level down is a fifth darker, so
+// unlit caves are close to black
+fn light_brightness(level: f32) -> f32 {
+    return pow(0.8, (1.0 - level) * 15.0);
+}
+
+// how bright a face corner with an ambient occlusion of `occlusion`, scaled to 0..1, is. Even a
+// corner tucked in between two blocks keeps some of its light
+fn occlusion_brightness(occlusion: f32) -> f32 {
+    return 0.4 + 0.6 * occlusion;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = face_uv(in.local_position, in.normal);
+    // the sampler repeats the texture, the gradients of the unwrapped coordinates keep the
+    // block edges free of seams
+    let color = textureSampleGrad(block_textures, block_sampler, uv, in.layer, dpdx(uv), dpdy(uv));
+    if color.a < material.alpha_cutoff {
+        discard;
+    }
+    let brightness = light_brightness(max(in.light.r * material.daylight, in.light.g)) * occlusion_brightness(in.light.b);
+    return vec4<f32>(color.rgb * face_shade(in.normal) * brightness, color.a);
+}
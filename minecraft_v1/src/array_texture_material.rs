@@ -0,0 +1,89 @@
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayout},
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef,
+            SpecializedMeshPipelineError, VertexFormat,
+        },
+    },
+};
+
+/// The layer of the [`TextureArray`](crate::load_texture_atlas::TextureArray) shown on the face a
+/// vertex belongs to.
+pub const ATTRIBUTE_TEXTURE_LAYER: MeshVertexAttribute =
+    MeshVertexAttribute::new("TextureLayer", 988_540_917, VertexFormat::Uint32);
+
+const SHADER: &str = "shaders/array_texture.wgsl";
+
+pub struct ArrayTextureMaterialPlugin;
+
+impl Plugin for ArrayTextureMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<ArrayTextureMaterial>::default());
+    }
+}
+
+/// Draws block faces with the layer of a 2D array texture picked by each vertex's
+/// [`ATTRIBUTE_TEXTURE_LAYER`]. The texture repeats once per block, so merged quads keep their
+/// texture the same size as on single blocks. Faces are as bright as the sky or block light in
+/// their vertex colours, whichever is stronger, and darker towards occluded corners. The
+/// material is unlit: that light is all the faces take, the [`DirectionalLight`]s and the
+/// [`AmbientLight`] of the scene don't reach them.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct ArrayTextureMaterial {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub array_texture: Handle<Image>,
+    /// how much of the sky light reaches the faces, from 0 to 1, lower at night
+    #[uniform(2)]
+    pub daylight: f32,
+    /// pixels less opaque than this are left out, set from `alpha_mode`
+    #[uniform(2)]
+    alpha_cutoff: f32,
+    /// how the faces are blended with what is behind them
+    alpha_mode: AlphaMode,
+}
+
+impl ArrayTextureMaterial {
+    /// a material drawing faces from `array_texture` in full daylight, blended with what is behind
+    /// them by `alpha_mode`
+    pub fn new(array_texture: Handle<Image>, alpha_mode: AlphaMode) -> Self {
+        let alpha_cutoff = match alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            _ => 0.0,
+        };
+        Self { array_texture, daylight: 1.0, alpha_cutoff, alpha_mode }
+    }
+}
+
+impl Material for ArrayTextureMaterial {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn vertex_shader() -> ShaderRef {
+        SHADER.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            ATTRIBUTE_TEXTURE_LAYER.at_shader_location(2),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(3),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::block_targeting::{target_block, TargetedBlock};
+use crate::block_types::BlockRegistry;
+use crate::chunk_manager::{ChunkManager, Position};
+use crate::inventory::Inventory;
+use crate::player_movement::Player;
+
+const BREAK_BUTTON: MouseButton = MouseButton::Left;
+const PLACE_BUTTON: MouseButton = MouseButton::Right;
+/// selects the hotbar slot holding the targeted block
+const PICK_BUTTON: MouseButton = MouseButton::Middle;
+const BREAK_PROGRESS_COLOR: Color = Color::RED;
+
+/// Lets the [`Player`] break the block it is looking at by holding the left mouse button, and
+/// place the block in the selected hotbar slot of the [`Inventory`] against it with the right
+/// mouse button. Broken blocks go into the inventory.
+pub struct BlockEditingPlugin;
+
+impl Plugin for BlockEditingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<BreakProgress>()
+            .add_systems(
+                Update,
+                (pick_block, break_block, place_block, draw_break_progress).chain().after(target_block),
+            );
+    }
+}
+
+/// The block being broken and for how many seconds the break button was held down on it.
+#[derive(Resource, Default)]
+struct BreakProgress(Option<(Position, f32)>);
+
+/// The mouse buttons, as long as the mouse is captured by the game, so clicks to focus the
+/// window don't edit blocks.
+#[derive(SystemParam)]
+pub struct EditButtons<'w, 's> {
+    buttons: Res<'w, Input<MouseButton>>,
+    window: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+impl EditButtons<'_, '_> {
+    fn grabbed(&self) -> bool {
+        self.window
+            .get_single()
+            .is_ok_and(|window| window.cursor.grab_mode != CursorGrabMode::None)
+    }
+
+    fn pressed(&self, button: MouseButton) -> bool {
+        self.grabbed() && self.buttons.pressed(button)
+    }
+
+    fn just_pressed(&self, button: MouseButton) -> bool {
+        self.grabbed() && self.buttons.just_pressed(button)
+    }
+}
+
+fn pick_block(
+    buttons: EditButtons,
+    targeted: Res<TargetedBlock>,
+    chunks: Res<ChunkManager>,
+    mut inventory: ResMut<Inventory>,
+) {
+    if !buttons.just_pressed(PICK_BUTTON) {
+        return;
+    }
+    let Some(block) = targeted.0.and_then(|hit| chunks.get_block(hit.position)) else {
+        return;
+    };
+    if let Some(slot) = inventory.hotbar_slot_of(block) {
+        inventory.select(slot);
+    }
+}
+
+/// Break the targeted block once the break button was held down on it for as many seconds as
+/// the block's hardness, and put it into the inventory. Blocks that don't fit are lost. Looking
+/// at another block starts over.
+fn break_block(
+    time: Res<Time>,
+    buttons: EditButtons,
+    targeted: Res<TargetedBlock>,
+    registry: Res<BlockRegistry>,
+    mut chunks: ResMut<ChunkManager>,
+    mut progress: ResMut<BreakProgress>,
+    mut inventory: ResMut<Inventory>,
+) {
+    let Some(hit) = targeted.0.filter(|_| buttons.pressed(BREAK_BUTTON)) else {
+        progress.0 = None;
+        return;
+    };
+    let Some(block) = chunks.get_block(hit.position) else {
+        progress.0 = None;
+        return;
+    };
+    let held = match progress.0 {
+        Some((position, held)) if position == hit.position => held + time.delta_seconds(),
+        _ => time.delta_seconds(),
+    };
+    let definition = registry.get(block);
+    if held >= definition.hardness {
+        chunks.remove_block(hit.position);
+        inventory.add(block, 1, definition.max_stack);
+        progress.0 = None;
+    } else {
+        progress.0 = Some((hit.position, held));
+    }
+}
+
+/// Place a block from the selected hotbar slot against the face of the targeted block, unless
+/// it would end up inside the player or replace another block that isn't air or a fluid.
+pub fn place_block(
+    buttons: EditButtons,
+    targeted: Res<TargetedBlock>,
+    registry: Res<BlockRegistry>,
+    mut inventory: ResMut<Inventory>,
+    mut chunks: ResMut<ChunkManager>,
+    player: Query<&Transform, With<Player>>,
+) {
+    if !buttons.just_pressed(PLACE_BUTTON) {
+        return;
+    }
+    let (Some(hit), Some(stack)) = (targeted.0, inventory.selected_stack()) else {
+        return;
+    };
+    let position = hit.position + hit.side.offset();
+    let Some(replaced) = chunks.get_block(position) else {
+        return;
+    };
+    if registry.is_targetable(replaced) {
+        return;
+    }
+    let in_the_way = registry.get(stack.block).solid
+        && player
+            .iter()
+            .any(|transform| Player::bounding_box(transform.translation).intersects_block(position));
+    if !in_the_way && chunks.set_block(position, stack.block).is_some() {
+        inventory.take_selected();
+    }
+}
+
+/// show how far the targeted block is from breaking, as a box growing inside its outline
+fn draw_break_progress(
+    progress: Res<BreakProgress>,
+    chunks: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut gizmos: Gizmos,
+) {
+    let Some((position, held)) = progress.0 else {
+        return;
+    };
+    let Some(block) = chunks.get_block(position) else {
+        return;
+    };
+    let fraction = (held / registry.get(block).hardness).clamp(0.0, 1.0);
+    let centre = Vec3::from(position) + Vec3::splat(0.5);
+    gizmos.cuboid(
+        Transform::from_translation(centre).with_scale(Vec3::splat(fraction)),
+        BREAK_PROGRESS_COLOR,
+    );
+}
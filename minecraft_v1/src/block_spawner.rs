@@ -1,478 +1,396 @@
 use std::collections::HashMap;
 
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
-    render::{render_resource::PrimitiveTopology, mesh::Indices}
+    render::mesh::VertexAttributeValues,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
 };
-// import meshvertextattribute
 use bevy_meshem::prelude::*;
-use crate::{load_texture_atlas::TextureAtlas, block_types::BlockType};
+use crate::{load_texture_atlas::{load_texture_atlas, TextureArray}, block_types::{BlockRegistry, RenderMode}};
+use crate::array_texture_material::ArrayTextureMaterial;
+use crate::chunk_streaming::{ChunkLoaded, ChunkUnloaded};
+use crate::lighting::light_loaded_chunks;
 use crate::chunk_manager::*;
+use crate::chunk_mesher::{mesh_chunk, update_chunk_mesh, ChunkMeshes, MeshingAlgorithm, MeshingOptions};
 
-/// Constants for us to use.
-const _FACTOR: usize = 100;
-const _MESHING_ALGORITHM: MeshingAlgorithm = MeshingAlgorithm::Culling;
-
+/// how new chunks are meshed
+const MESHING: MeshingOptions = MeshingOptions {
+    algorithm: MeshingAlgorithm::Greedy,
+    ambient_occlusion: true,
+};
+/// The meshes of a chunk. The entity with this component sits at the chunk's origin, and has a
+/// child drawing the mesh of each [`RenderMode`]. Changing `options`, see [`SetMeshingOptions`],
+/// rebuilds the meshes with the new options.
 #[derive(Component)]
 struct Meshy {
-    _ma: MeshingAlgorithm,
-    _meta: MeshMD<u16>,
+    /// the chunk coordinate of the chunk these meshes were built from
+    chunk: Position,
+    options: MeshingOptions,
+    /// kept for chunks meshed with [`MeshingAlgorithm::Incremental`], so edits can be patched
+    /// into their opaque mesh
+    metadata: Option<MeshMD<u16>>,
+    /// the child drawing the mesh of each render mode, indexed by [`RenderMode`]
+    layers: [Entity; 3],
 }
 
-#[derive(Event, Default)]
-struct RegenerateMesh;
+/// Sent to mesh the chunk at chunk coordinate `chunk` with `options` from now on.
+#[derive(Event)]
+pub struct SetMeshingOptions {
+    pub chunk: Position,
+    pub options: MeshingOptions,
+}
 
+/// The materials chunk meshes are rendered with, one for each [`RenderMode`]. Anything else
+/// built out of block faces, like a falling block, is drawn with them too.
 #[derive(Resource)]
-struct BlockRegistry {
-    block: Vec<Mesh>,
-}
+pub struct ChunkMaterial([Handle<ArrayTextureMaterial>; 3]);
 
-/// The important part! Without implementing a [`VoxelRegistry`], you can't use the function.
-impl VoxelRegistry for BlockRegistry {
-    /// The type of our Voxel, the example uses u16 for Simplicity but you may have a struct
-    /// Block { Name: ..., etc ...}, and you'll define that as the type, but encoding the block
-    /// data onto simple type like u16 or u64 is probably prefferable.
-    type Voxel = u16;
-    /// The get_mesh function, probably the most important function in the
-    /// [`VoxelRegistry`], it is what allows us to  quickly access the Mesh of each Voxel.
-    fn get_mesh(&self, voxel: &Self::Voxel) -> VoxelMesh<&Mesh> {
-        if *voxel == 0 {
-            return VoxelMesh::Null;
-        }
-        VoxelMesh::NormalCube(&self.block[*voxel as usize])
-    }
-    /// Important function that tells our Algorithm if the Voxel is "full", for example, the Air
-    /// in minecraft is not "full", but it is still on the chunk data, to singal there is nothing.
-    fn is_covering(&self, voxel: &Self::Voxel, _side: Face) -> bool {
-        return *voxel != 0;
-    }
-    /// The center of the Mesh, out mesh is defined in src/default_block.rs, just a constant.
-    fn get_center(&self) -> [f32; 3] {
-        return [0.5, 0.5, 0.5];
+impl ChunkMaterial {
+    /// spawn an entity of its own drawing `mesh` with the material for `mode`
+    pub fn spawn(&self, commands: &mut Commands, mode: RenderMode, mesh: Handle<Mesh>, transform: Transform) -> Entity {
+        commands
+            .spawn(MaterialMeshBundle {
+                mesh,
+                material: self.0[mode as usize].clone(),
+                transform,
+                ..Default::default()
+            })
+            .id()
     }
-    /// The dimensions of the Mesh, out mesh is defined in src/default_block.rs, just a constant.
-    fn get_voxel_dimensions(&self) -> [f32; 3] {
-        return [1.0, 1.0, 1.0];
+
+    /// spawn an entity drawing `mesh` with the material for `mode`
+    fn spawn_layer(&self, parent: &mut ChildBuilder, mode: RenderMode, mesh: Handle<Mesh>, transform: Transform, visibility: Visibility) -> Entity {
+        parent
+            .spawn(MaterialMeshBundle {
+                mesh,
+                material: self.0[mode as usize].clone(),
+                transform,
+                visibility,
+                ..Default::default()
+            })
+            .id()
     }
-    /// The attributes we want to take from out voxels, note that using a lot of different
-    /// attributes will likely lead to performance problems and unpredictible behaviour.
-    /// We chose these 3 because they are very common, the algorithm does preserve UV data.
-    fn all_attributes(&self) -> Vec<bevy::render::mesh::MeshVertexAttribute> {
-        return vec![
-            Mesh::ATTRIBUTE_POSITION,
-            Mesh::ATTRIBUTE_UV_0,
-            // generate a custom MeshVertexAttribute which maps a 16x16 texture to each face
-            Mesh::ATTRIBUTE_NORMAL,
-        ];
+}
+
+pub struct BlockSpawnerPlugin;
+
+impl Plugin for BlockSpawnerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ChunkEntities>()
+            .init_resource::<MeshTasks>()
+            .add_systems(Startup, setup_chunk_material.after(load_texture_atlas))
+            .add_systems(Update, (despawn_chunk_meshes, queue_loaded_chunk_meshes).chain().after(light_loaded_chunks))
+            .add_event::<SetMeshingOptions>()
+            .add_systems(Update, ((toggle_meshing_algorithm, toggle_ambient_occlusion), set_meshing_options, remesh_on_options_change).chain())
+            .add_systems(PostUpdate, (regenerate_meshes, receive_chunk_meshes).chain().after(send_remesh_events))
+            .init_resource::<BlockRegistry>();
     }
 }
 
-fn _spawn_culled_grid(
-    breg: Res<BlockRegistry>,
-    texture_atlas: Res<TextureAtlas>,
+fn setup_chunk_material(
     mut commands: Commands,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    // wireframe_config: ResMut<WireframeConfig>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ArrayTextureMaterial>>,
+    texture_array: Res<TextureArray>,
 ) {
-    let grid = [1; _FACTOR * _FACTOR * _FACTOR];
-    let dims: Dimensions = (_FACTOR, _FACTOR, _FACTOR);
+    let materials = RenderMode::ALL.map(|mode| {
+        let array_texture = texture_array.handle.clone().expect("the texture array is loaded at startup");
+        materials.add(ArrayTextureMaterial::new(array_texture, mode.alpha_mode()))
+    });
+    commands.insert_resource(ChunkMaterial(materials));
+}
 
-    let (culled_mesh, metadata) = mesh_grid(
-        dims,
-        // Automatically cull the bottom when generating the mesh
-        &[Bottom],
-        &grid,
-        breg.into_inner(),
-        _MESHING_ALGORITHM,
-        None,
-    )
-    .unwrap();
-    let texture_handle = texture_atlas.handle.clone().unwrap();
-    let culled_mesh_handle: Handle<Mesh> = meshes.add(culled_mesh.clone());
-    commands.spawn((
-        PbrBundle {
-            mesh: culled_mesh_handle,
-            material: materials.add(StandardMaterial {
-                reflectance: 0.0,
-                base_color_texture: Some(texture_handle),
-                ..default()
-            }),
-            ..default()
-        },
-        Meshy {
-            _ma: _MESHING_ALGORITHM,
-            _meta: metadata,
-        },
-    ));
+/// The mesh assets together with the material the chunk meshes among them are drawn with.
+#[derive(SystemParam)]
+struct ChunkMeshAssets<'w> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    material: Res<'w, ChunkMaterial>,
 }
 
-enum Side {
-    Top,
-    Bottom,
-    Forward,
-    Back,
-    Left,
-    Right,
+/// The entities showing the meshes of the chunks, with the [`Meshy`] on each of them and the
+/// mesh drawn by each of their layers.
+#[derive(SystemParam)]
+struct ChunkMeshEntities<'w, 's> {
+    entities: Res<'w, ChunkEntities>,
+    meshies: Query<'w, 's, &'static mut Meshy>,
+    layers: Query<'w, 's, &'static Handle<Mesh>>,
 }
 
-pub struct BlockSpawnerPlugin;
+/// The entity showing the meshes of each chunk, keyed by chunk coordinate.
+#[derive(Resource, Default)]
+struct ChunkEntities(HashMap<Position, Entity>);
 
-impl Plugin for BlockSpawnerPlugin {
-    fn build(&self, app: &mut App) {
-        app
-            .add_systems(Startup, spawn_chunk)
-            .insert_resource(BlockRegistry {
-                block: vec![
-                    Mesh::new(PrimitiveTopology::TriangleList),
-                    generate_voxel_mesh(
-                        [1.0, 1.0, 1.0],
-                        [64, 32],
-                        [
-                            (Top, [1, 28]),
-                            (Bottom, [1, 28]),
-                            (Forward, [1, 28]),
-                            (Back, [1, 28]),
-                            (Left, [1, 28]),
-                            (Right, [1, 28]),
-                        ], // texture,
-                        [0.5, 0.5, 0.5],
-                        0.05,
-                        Some(0.8),
-                        1.0,
-                    ),
-                ],
-            });
+/// The meshes of a chunk being built on the [`AsyncComputeTaskPool`].
+struct PendingMesh {
+    options: MeshingOptions,
+    task: Task<(ChunkMeshes, Option<MeshMD<u16>>)>,
+}
+
+/// The chunk meshes being built, keyed by chunk coordinate. Dropping a task cancels it.
+#[derive(Resource, Default)]
+struct MeshTasks(HashMap<Position, PendingMesh>);
+
+impl MeshTasks {
+    /// Start building the mesh of `chunk` from the blocks as they are now. A mesh already being
+    /// built for the chunk is cancelled, since it may be missing the latest changes.
+    fn queue(&mut self, chunks: &ChunkManager, registry: &BlockRegistry, chunk: Position, options: MeshingOptions) {
+        let Some(neighbourhood) = ChunkNeighbourhood::new(chunks, chunk) else {
+            return;
+        };
+        let snapshot = neighbourhood.snapshot();
+        let registry = registry.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            mesh_chunk(&snapshot.neighbourhood(), options, &registry)
+        });
+        self.0.insert(chunk, PendingMesh { options, task });
     }
 }
 
-/// spawn a 3d cube at the origin
-fn spawn_chunk(mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    _asset_server: Res<AssetServer>,
-    mut _materials: ResMut<Assets<StandardMaterial>>,
-    mut chunks: ResMut<ChunkManager>,
+/// Start meshing every chunk that was loaded, and rebuild the meshes of the chunks next to it,
+/// since the faces on their borders may now be hidden.
+fn queue_loaded_chunk_meshes(
+    mut loaded: EventReader<ChunkLoaded>,
+    mut tasks: ResMut<MeshTasks>,
+    entities: Res<ChunkEntities>,
+    chunks: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    query: Query<&Meshy>,
 ) {
-    const CHUNK_X: u16 = 16;
-    const CHUNK_Y: u16 = 256;
-    const CHUNK_Z: u16 = 16;
+    let loaded: Vec<Position> = loaded.read().map(|event| event.0).collect();
+    for &chunk_position in &loaded {
+        tasks.queue(&chunks, &registry, chunk_position, MESHING);
+
+        for offset in [Position::new(1, 0, 0), Position::new(-1, 0, 0), Position::new(0, 0, 1), Position::new(0, 0, -1)] {
+            let neighbour = chunk_position + offset;
+            if loaded.contains(&neighbour) {
+                continue;
+            }
+            let options = match tasks.0.get(&neighbour) {
+                Some(pending) => pending.options,
+                None => match entities.0.get(&neighbour).and_then(|entity| query.get(*entity).ok()) {
+                    Some(meshy) => meshy.options,
+                    None => continue,
+                },
+            };
+            tasks.queue(&chunks, &registry, neighbour, options);
+        }
+    }
+}
 
-    const START_X: f32 = 0.0;
-    const START_Y: f32 = 0.0;
-    const START_Z: f32 = 0.0;
-    
-    let mut chunk = Chunk {
-        position: Vec3::new(START_X, START_Y, START_Z),
-        blocks: HashMap::default(),
+/// Translucent meshes are sorted back to front by the position of their entity, so each one is
+/// moved to sit around its entity, which is placed in the middle of the faces. Only whole blocks
+/// are moved, so the textures worked out from the positions stay lined up. Returns where the
+/// entity goes relative to the chunk.
+fn centre_mesh(mesh: &mut Mesh) -> Vec3 {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+        return Vec3::ZERO;
     };
+    let (min, max) = positions.iter().fold((Vec3::MAX, Vec3::MIN), |(min, max), position| {
+        (min.min(Vec3::from(*position)), max.max(Vec3::from(*position)))
+    });
+    if positions.is_empty() {
+        return Vec3::ZERO;
+    }
+    let centre = ((min + max) / 2.0).round();
+    for position in positions.iter_mut() {
+        *position = (Vec3::from(*position) - centre).into();
+    }
+    centre
+}
+
+/// chunk meshes without any faces aren't drawn
+fn layer_visibility(mesh: &Mesh) -> Visibility {
+    if mesh.count_vertices() == 0 {
+        Visibility::Hidden
+    } else {
+        Visibility::Inherited
+    }
+}
 
-    for x in 0..CHUNK_X {
-        for y in 0..CHUNK_Y {
-            for z in 0..CHUNK_Z {
-                let block = Block {
-                    local_position: Vec3::new(x as f32, y as f32, z as f32),
-                    absolute_position: Vec3::new(x as f32 + START_X, y as f32 + START_Y, z as f32 + START_Z),
-                    is_visible: true,
-                    block_type: BlockType::Dirt,
-                    is_transparent: false,
+/// Show the chunk meshes that finished building, spawning the entities of chunks that didn't
+/// have meshes yet.
+fn receive_chunk_meshes(
+    mut commands: Commands,
+    mut tasks: ResMut<MeshTasks>,
+    mut entities: ResMut<ChunkEntities>,
+    mut assets: ChunkMeshAssets,
+    chunks: Res<ChunkManager>,
+    mut query: Query<&mut Meshy>,
+    mut layers: Query<(&Handle<Mesh>, &mut Transform, &mut Visibility)>,
+) {
+    let finished: Vec<Position> = tasks
+        .0
+        .iter()
+        .filter(|(_, pending)| pending.task.is_finished())
+        .map(|(position, _)| *position)
+        .collect();
+    for chunk_position in finished {
+        let pending = tasks.0.remove(&chunk_position).expect("finished tasks are still queued");
+        let (meshes, metadata) = block_on(pending.task);
+        let meshes: Vec<(RenderMode, Mesh, Vec3)> = meshes
+            .into_meshes()
+            .map(|(mode, mut mesh)| {
+                let offset = match mode {
+                    RenderMode::Translucent => centre_mesh(&mut mesh),
+                    _ => Vec3::ZERO,
                 };
-                chunk.blocks.insert(
-                    Position::new(x as isize, y as isize, z as isize),
-                    block,
-                );
+                (mode, mesh, offset)
+            })
+            .collect();
+
+        if let Some(mut meshy) = entities.0.get(&chunk_position).and_then(|entity| query.get_mut(*entity).ok()) {
+            for (mode, mesh, offset) in meshes {
+                let Ok((handle, mut transform, mut visibility)) = layers.get_mut(meshy.layers[mode as usize]) else {
+                    continue;
+                };
+                transform.translation = offset;
+                *visibility = layer_visibility(&mesh);
+                if let Some(old_mesh) = assets.meshes.get_mut(handle) {
+                    *old_mesh = mesh;
+                }
             }
+            meshy.bypass_change_detection().metadata = metadata;
+            continue;
         }
-    }
-    for block in chunk.blocks.values() {
-        let block_above = chunk.blocks.get(&(block.local_position - Vec3::new(0.0, -1.0, 0.0)).into());
-        let block_below = chunk.blocks.get(&(block.local_position - Vec3::new(0.0, 1.0, 0.0)).into());
-        let block_left = chunk.blocks.get(&(block.local_position - Vec3::new(1.0, 0.0, 0.0)).into());
-        let block_right = chunk.blocks.get(&(block.local_position + Vec3::new(1.0, 0.0, 0.0)).into());
-        let block_front = chunk.blocks.get(&(block.local_position + Vec3::new(0.0, 0.0, 1.0)).into());
-        let block_back = chunk.blocks.get(&(block.local_position - Vec3::new(0.0, 0.0, 1.0)).into());
 
-        let mut sides = Vec::new();
+        let Some(chunk) = chunks.chunks.get(&chunk_position) else {
+            continue;
+        };
+        let mut layer_entities = Vec::with_capacity(meshes.len());
+        let entity = commands
+            .spawn(SpatialBundle::from_transform(Transform::from_translation(chunk.position)))
+            .with_children(|parent| {
+                for (mode, mesh, offset) in meshes {
+                    let visibility = layer_visibility(&mesh);
+                    let transform = Transform::from_translation(offset);
+                    let mesh = assets.meshes.add(mesh);
+                    layer_entities.push(assets.material.spawn_layer(parent, mode, mesh, transform, visibility));
+                }
+            })
+            .id();
+        commands.entity(entity).insert(Meshy {
+            chunk: chunk_position,
+            options: pending.options,
+            metadata,
+            layers: layer_entities.try_into().expect("a chunk has a mesh for every render mode"),
+        });
+        entities.0.insert(chunk_position, entity);
+    }
+}
 
-        if block_above.is_none() || (block_above.is_some() && block_above.unwrap().is_transparent && block_above.unwrap().is_visible) {
-            sides.push(Side::Top);
-        }
-        if block_below.is_none() || (block_below.is_some() && block_below.unwrap().is_transparent && block_below.unwrap().is_visible) {
-            sides.push(Side::Bottom);
-        }
-        if block_left.is_none() || (block_left.is_some() && block_left.unwrap().is_transparent && block_left.unwrap().is_visible) {
-            sides.push(Side::Left);
-        }
-        if block_right.is_none() || (block_right.is_some() && block_right.unwrap().is_transparent && block_right.unwrap().is_visible) {
-            sides.push(Side::Right);
-        }
-        if block_front.is_none() || (block_front.is_some() && block_front.unwrap().is_transparent && block_front.unwrap().is_visible) {
-            sides.push(Side::Forward);
-        }
-        if block_back.is_none() || (block_back.is_some() && block_back.unwrap().is_transparent && block_back.unwrap().is_visible) {
-            sides.push(Side::Back);
+/// despawn the meshes of chunks that were unloaded, and stop building the ones still queued
+fn despawn_chunk_meshes(
+    mut commands: Commands,
+    mut unloaded: EventReader<ChunkUnloaded>,
+    mut entities: ResMut<ChunkEntities>,
+    mut tasks: ResMut<MeshTasks>,
+) {
+    for ChunkUnloaded(chunk_position) in unloaded.read() {
+        tasks.0.remove(chunk_position);
+        if let Some(entity) = entities.0.remove(chunk_position) {
+            commands.entity(entity).despawn_recursive();
         }
-        
-        let cube_mesh = create_cube_mesh(vec![Side::Top, Side::Bottom, Side::Left, Side::Right, Side::Forward, Side::Back]);
-        let cube_handle = meshes.add(cube_mesh);
-        commands.spawn(PbrBundle {
-            mesh: cube_handle,
-            material: _materials.add(StandardMaterial {
-                cull_mode: None,
-                base_color: Color::rgb(0.8, 0.7, 0.6),
-                ..Default::default()
-            }),
-            transform: Transform::from_translation(block.absolute_position),
-            ..Default::default()
+    }
+}
+
+/// cycle every chunk through the meshing algorithms when G is pressed
+fn toggle_meshing_algorithm(input: Res<Input<KeyCode>>, query: Query<&Meshy>, mut events: EventWriter<SetMeshingOptions>) {
+    if !input.just_pressed(KeyCode::G) {
+        return;
+    }
+    for meshy in query.iter() {
+        let algorithm = match meshy.options.algorithm {
+            MeshingAlgorithm::Culling => MeshingAlgorithm::Greedy,
+            MeshingAlgorithm::Greedy => MeshingAlgorithm::Incremental,
+            MeshingAlgorithm::Incremental => MeshingAlgorithm::Culling,
+        };
+        events.send(SetMeshingOptions {
+            chunk: meshy.chunk,
+            options: MeshingOptions { algorithm, ..meshy.options },
         });
     }
+}
 
-    chunks.chunks.insert(Position::new(START_X as isize, START_Y as isize, START_Z as isize), chunk);
+/// switch ambient occlusion on or off for every chunk when O is pressed
+fn toggle_ambient_occlusion(input: Res<Input<KeyCode>>, query: Query<&Meshy>, mut events: EventWriter<SetMeshingOptions>) {
+    if !input.just_pressed(KeyCode::O) {
+        return;
+    }
+    for meshy in query.iter() {
+        events.send(SetMeshingOptions {
+            chunk: meshy.chunk,
+            options: MeshingOptions {
+                ambient_occlusion: !meshy.options.ambient_occlusion,
+                ..meshy.options
+            },
+        });
+    }
 }
 
-#[rustfmt::skip]
-fn create_cube_mesh(sides: Vec<Side>) -> Mesh {
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    let mut attribute_positions = Vec::new();
-    let mut attribute_uvs = Vec::new();
-    let mut attribute_normals = Vec::new();
-    let mut indices = Vec::new();
+fn set_meshing_options(
+    mut events: EventReader<SetMeshingOptions>,
+    entities: Res<ChunkEntities>,
+    mut query: Query<&mut Meshy>,
+) {
+    for SetMeshingOptions { chunk, options } in events.read() {
+        let Some(mut meshy) = entities.0.get(chunk).and_then(|entity| query.get_mut(*entity).ok()) else {
+            continue;
+        };
+        if meshy.options != *options {
+            meshy.options = *options;
+        }
+    }
+}
 
-    for side in sides {
-        match side {
-            Side::Top => {
-                attribute_positions.extend_from_slice(&[
-                    [-0.5, 0.5, -0.5], // vertex with index 0
-                    [0.5, 0.5, -0.5], // vertex with index 1
-                    [0.5, 0.5, 0.5], // etc. until 23
-                    [-0.5, 0.5, 0.5],
-                ]);
-                attribute_uvs.extend_from_slice(&[
-                    [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-                ]);
-                attribute_normals.extend_from_slice(&[
-                    // Normals for the top side (towards +y)
-                    [0.0, 1.0, 0.0],
-                    [0.0, 1.0, 0.0],
-                    [0.0, 1.0, 0.0],
-                    [0.0, 1.0, 0.0],
-                ]);
-            },
-            Side::Bottom => {
-                attribute_positions.extend_from_slice(&[
-                    // bottom   (-y)
-                    [-0.5, -0.5, -0.5],
-                    [0.5, -0.5, -0.5],
-                    [0.5, -0.5, 0.5],
-                    [-0.5, -0.5, 0.5],
-                ]);
-                attribute_uvs.extend_from_slice(&[
-                    [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-                ]);
-                attribute_normals.extend_from_slice(&[
-                    [0.0, -1.0, 0.0],
-                    [0.0, -1.0, 0.0],
-                    [0.0, -1.0, 0.0],
-                    [0.0, -1.0, 0.0],
-                ]);
-            },
-            Side::Right => {
-                attribute_positions.extend_from_slice(&[
-                    // right    (+x)
-                    [0.5, -0.5, -0.5],
-                    [0.5, -0.5, 0.5],
-                    [0.5, 0.5, 0.5], // This vertex is at the same position as vertex with index 2, but they'll have different UV and normal
-                    [0.5, 0.5, -0.5],
-                ]);
-                attribute_uvs.extend_from_slice(&[
-                    [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-                ]);
-                attribute_normals.extend_from_slice(&[
-                    // Normals for the right side (towards +x)
-                    [1.0, 0.0, 0.0],
-                    [1.0, 0.0, 0.0],
-                    [1.0, 0.0, 0.0],
-                    [1.0, 0.0, 0.0],
-                ]);
-            },
-            Side::Left => {
-                attribute_positions.extend_from_slice(&[
-                    // left     (-x)
-                    [-0.5, -0.5, -0.5],
-                    [-0.5, -0.5, 0.5],
-                    [-0.5, 0.5, 0.5],
-                    [-0.5, 0.5, -0.5],
-                ]);
-                attribute_uvs.extend_from_slice(&[
-                    [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-                ]);
-                attribute_normals.extend_from_slice(&[
-                    [-1.0, 0.0, 0.0],
-                    [-1.0, 0.0, 0.0],
-                    [-1.0, 0.0, 0.0],
-                    [-1.0, 0.0, 0.0],
-                ]);
-            },
-            Side::Back => {
-                attribute_positions.extend_from_slice(&[
-                    // back     (+z)
-                    [-0.5, -0.5, 0.5],
-                    [-0.5, 0.5, 0.5],
-                    [0.5, 0.5, 0.5],
-                    [0.5, -0.5, 0.5],
-                ]);
-                attribute_uvs.extend_from_slice(&[
-                    [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-                ]);
-                attribute_normals.extend_from_slice(&[
-                    [0.0, 0.0, 1.0],
-                    [0.0, 0.0, 1.0],
-                    [0.0, 0.0, 1.0],
-                    [0.0, 0.0, 1.0],
-                ]);
-            },
-            Side::Forward => {
-                attribute_positions.extend_from_slice(&[
-                    // forward  (-z)
-                    [-0.5, -0.5, -0.5],
-                    [-0.5, 0.5, -0.5],
-                    [0.5, 0.5, -0.5],
-                    [0.5, -0.5, -0.5],
-                ]);
-                attribute_uvs.extend_from_slice(&[
-                    [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-                ]);
-                attribute_normals.extend_from_slice(&[
-                    // Normals for the forward side (towards -z)
-                    [0.0, 0.0, -1.0],
-                    [0.0, 0.0, -1.0],
-                    [0.0, 0.0, -1.0],
-                    [0.0, 0.0, -1.0],
-                ]);
+/// rebuild the mesh of every chunk whose meshing options were changed
+fn remesh_on_options_change(
+    chunks: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut tasks: ResMut<MeshTasks>,
+    query: Query<Ref<Meshy>>,
+) {
+    for meshy in query.iter() {
+        if meshy.is_changed() && !meshy.is_added() {
+            tasks.queue(&chunks, &registry, meshy.chunk, meshy.options);
+        }
+    }
+}
+
+/// Bring the meshes of edited chunks up to date. Chunks that kept their `bevy_meshem` metadata
+/// get the edits patched into their opaque mesh right away where they can, every other chunk is
+/// meshed again from scratch.
+fn regenerate_meshes(
+    mut events: EventReader<RegenerateMesh>,
+    chunks: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut tasks: ResMut<MeshTasks>,
+    mut assets: ChunkMeshAssets,
+    mut chunk_entities: ChunkMeshEntities,
+) {
+    for event in events.read() {
+        // a mesh that is still being built was built from the blocks before the edit
+        if let Some(options) = tasks.0.get(&event.chunk).map(|pending| pending.options) {
+            tasks.queue(&chunks, &registry, event.chunk, options);
+            continue;
+        }
+        let ChunkMeshEntities { entities, meshies, layers } = &mut chunk_entities;
+        let Some(mut meshy) = entities.0.get(&event.chunk).and_then(|entity| meshies.get_mut(*entity).ok()) else {
+            continue;
+        };
+        let Ok(handle) = layers.get(meshy.layers[RenderMode::Opaque as usize]) else {
+            continue;
+        };
+        let Some(neighbourhood) = ChunkNeighbourhood::new(&chunks, event.chunk) else {
+            continue;
+        };
+        let meshy = meshy.bypass_change_detection();
+        if let (Some(metadata), Some(mesh), false) = (meshy.metadata.as_mut(), assets.meshes.get_mut(handle), event.edits.is_empty()) {
+            let ambient_occlusion = meshy.options.ambient_occlusion;
+            if update_chunk_mesh(mesh, metadata, &neighbourhood, &event.edits, &registry, ambient_occlusion) {
+                continue;
             }
         }
-        let start_index = attribute_positions.len() as u32;
-        indices.extend_from_slice(&[
-            start_index, start_index + 3, start_index + 1,
-            start_index + 1, start_index + 3, start_index + 2,
-]);
+        tasks.queue(&chunks, &registry, event.chunk, meshy.options);
     }
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, attribute_positions);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, attribute_uvs);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, attribute_normals);
-    mesh.set_indices(Some(Indices::U32(indices)));
-    return mesh;
-    /*Mesh::new(PrimitiveTopology::TriangleList)
-    .with_inserted_attribute(
-        Mesh::ATTRIBUTE_POSITION,
-        // Each array is an [x, y, z] coordinate in local space.
-        // Meshes always rotate around their local [0, 0, 0] when a rotation is applied to their Transform.
-        // By centering our mesh around the origin, rotating the mesh preserves its center of mass.
-        vec![
-            // top (facing towards +y)
-            [-0.5, 0.5, -0.5], // vertex with index 0
-            [0.5, 0.5, -0.5], // vertex with index 1
-            [0.5, 0.5, 0.5], // etc. until 23
-            [-0.5, 0.5, 0.5],
-            // bottom   (-y)
-            [-0.5, -0.5, -0.5],
-            [0.5, -0.5, -0.5],
-            [0.5, -0.5, 0.5],
-            [-0.5, -0.5, 0.5],
-            // right    (+x)
-            [0.5, -0.5, -0.5],
-            [0.5, -0.5, 0.5],
-            [0.5, 0.5, 0.5], // This vertex is at the same position as vertex with index 2, but they'll have different UV and normal
-            [0.5, 0.5, -0.5],
-            // left     (-x)
-            [-0.5, -0.5, -0.5],
-            [-0.5, -0.5, 0.5],
-            [-0.5, 0.5, 0.5],
-            [-0.5, 0.5, -0.5],
-            // back     (+z)
-            [-0.5, -0.5, 0.5],
-            [-0.5, 0.5, 0.5],
-            [0.5, 0.5, 0.5],
-            [0.5, -0.5, 0.5],
-            // forward  (-z)
-            [-0.5, -0.5, -0.5],
-            [-0.5, 0.5, -0.5],
-            [0.5, 0.5, -0.5],
-            [0.5, -0.5, -0.5],
-        ],
-    )
-    // Set-up UV coordinated to point to the upper (V < 0.5), "dirt+grass" part of the texture.
-    // Take a look at the custom image (assets/textures/array_texture.png)
-    // so the UV coords will make more sense
-    // Note: (0.0, 0.0) = Top-Left in UV mapping, (1.0, 1.0) = Bottom-Right in UV mapping
-    .with_inserted_attribute(
-        Mesh::ATTRIBUTE_UV_0,
-        vec![
-            // Assigning the UV coords for the top side.
-            [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-            // Assigning the UV coords for the bottom side.
-            [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-            // Assigning the UV coords for the right side.
-            [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-            // Assigning the UV coords for the left side.
-            [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-            // Assigning the UV coords for the back side.
-            [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-            // Assigning the UV coords for the forward side.
-            [0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0],
-        ],
-    )
-    // For meshes with flat shading, normals are orthogonal (pointing out) from the direction of
-    // the surface.
-    // Normals are required for correct lighting calculations.
-    // Each array represents a normalized vector, which length should be equal to 1.0.
-    .with_inserted_attribute(
-        Mesh::ATTRIBUTE_NORMAL,
-        vec![
-            // Normals for the top side (towards +y)
-            [0.0, 1.0, 0.0],
-            [0.0, 1.0, 0.0],
-            [0.0, 1.0, 0.0],
-            [0.0, 1.0, 0.0],
-            // Normals for the bottom side (towards -y)
-            [0.0, -1.0, 0.0],
-            [0.0, -1.0, 0.0],
-            [0.0, -1.0, 0.0],
-            [0.0, -1.0, 0.0],
-            // Normals for the right side (towards +x)
-            [1.0, 0.0, 0.0],
-            [1.0, 0.0, 0.0],
-            [1.0, 0.0, 0.0],
-            [1.0, 0.0, 0.0],
-            // Normals for the left side (towards -x)
-            [-1.0, 0.0, 0.0],
-            [-1.0, 0.0, 0.0],
-            [-1.0, 0.0, 0.0],
-            [-1.0, 0.0, 0.0],
-            // Normals for the back side (towards +z)
-            [0.0, 0.0, 1.0],
-            [0.0, 0.0, 1.0],
-            [0.0, 0.0, 1.0],
-            [0.0, 0.0, 1.0],
-            // Normals for the forward side (towards -z)
-            [0.0, 0.0, -1.0],
-            [0.0, 0.0, -1.0],
-            [0.0, 0.0, -1.0],
-            [0.0, 0.0, -1.0],
-        ],
-    )
-    // Create the triangles out of the 24 vertices we created.
-    // To construct a square, we need 2 triangles, therefore 12 triangles in total.
-    // To construct a triangle, we need the indices of its 3 defined vertices, adding them one
-    // by one, in a counter-clockwise order (relative to the position of the viewer, the order
-    // should appear counter-clockwise from the front of the triangle, in this case from outside the cube).
-    // Read more about how to correctly build a mesh manually in the Bevy documentation of a Mesh,
-    // further examples and the implementation of the built-in shapes.
-    .with_indices(Some(Indices::U32(vec![
-        0,3,1 , 1,3,2, // triangles making up the top (+y) facing side.
-        4,5,7 , 5,6,7, // bottom (-y)
-        8,11,9 , 9,11,10, // right (+x)
-        12,13,15 , 13,14,15, // left (-x)
-        16,19,17 , 17,19,18, // back (+z)
-        20,21,23 , 21,22,23, // forward (-z)
-    ])));*/
-}
\ No newline at end of file
+}
@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+
+use crate::block_types::BlockRegistry;
+use crate::chunk_manager::{ChunkManager, Position};
+use crate::chunk_mesher::Side;
+use crate::player_movement::{move_player, Player};
+
+/// how far away from the camera blocks can be targeted, in blocks
+const REACH: f32 = 5.0;
+/// how much larger than a block the outline around the targeted block is, so it isn't hidden
+/// inside the block's faces
+const OUTLINE_SCALE: f32 = 1.005;
+const OUTLINE_COLOR: Color = Color::BLACK;
+
+/// Keeps track of the block the [`Player`] is looking at and draws an outline around it.
+pub struct BlockTargetingPlugin;
+
+impl Plugin for BlockTargetingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<TargetedBlock>()
+            .add_systems(Update, (target_block, draw_target_outline).chain().after(move_player));
+    }
+}
+
+/// Where a ray ran into a block.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RaycastHit {
+    /// world position of the block that was hit
+    pub position: Position,
+    /// the face of the block the ray entered it through
+    pub side: Side,
+    /// how far along the ray the block was hit
+    pub distance: f32,
+}
+
+/// The block the player is looking at within [`REACH`], if any.
+#[derive(Resource, Default)]
+pub struct TargetedBlock(pub Option<RaycastHit>);
+
+/// Walk the blocks along the ray from `origin` towards `direction` in the order the ray passes
+/// through them, and return the first one for which `is_hit` is true, up to `max_distance` away.
+/// The block `origin` lies in is never hit, so a ray starting inside a block can see out of it.
+pub fn raycast(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    is_hit: impl Fn(Position) -> bool,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    let start = origin.floor();
+    let mut block = [start.x as isize, start.y as isize, start.z as isize];
+    let mut step = [0; 3];
+    // how far along the ray the next block boundary on each axis is, and how far apart the
+    // boundaries on each axis are
+    let mut next_boundary = [f32::INFINITY; 3];
+    let mut boundary_distance = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            next_boundary[axis] = (start[axis] + 1.0 - origin[axis]) / direction[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            next_boundary[axis] = (start[axis] - origin[axis]) / direction[axis];
+        } else {
+            continue;
+        }
+        boundary_distance[axis] = 1.0 / direction[axis].abs();
+    }
+
+    loop {
+        let axis = (0..3)
+            .min_by(|a, b| next_boundary[*a].total_cmp(&next_boundary[*b]))
+            .expect("there are three axes");
+        let distance = next_boundary[axis];
+        if distance > max_distance {
+            return None;
+        }
+        block[axis] += step[axis];
+        next_boundary[axis] += boundary_distance[axis];
+
+        let position = Position::new(block[0], block[1], block[2]);
+        if is_hit(position) {
+            let side = match (axis, step[axis] > 0) {
+                (0, true) => Side::Left,
+                (0, false) => Side::Right,
+                (1, true) => Side::Bottom,
+                (1, false) => Side::Top,
+                (_, true) => Side::Forward,
+                (_, false) => Side::Back,
+            };
+            return Some(RaycastHit { position, side, distance });
+        }
+    }
+}
+
+/// find the block the player's camera is pointed at, looking through air and fluids
+pub fn target_block(
+    chunks: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut targeted: ResMut<TargetedBlock>,
+    query: Query<&Transform, With<Player>>,
+) {
+    let Ok(transform) = query.get_single() else {
+        targeted.0 = None;
+        return;
+    };
+    let hit = raycast(transform.translation, transform.forward(), REACH, |position| {
+        chunks.get_block(position).is_some_and(|block| registry.is_targetable(block))
+    });
+    if targeted.0 != hit {
+        targeted.0 = hit;
+    }
+}
+
+fn draw_target_outline(targeted: Res<TargetedBlock>, mut gizmos: Gizmos) {
+    if let Some(hit) = targeted.0 {
+        let centre = Vec3::from(hit.position) + Vec3::splat(0.5);
+        gizmos.cuboid(
+            Transform::from_translation(centre).with_scale(Vec3::splat(OUTLINE_SCALE)),
+            OUTLINE_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_types::BlockType;
+    use crate::chunk_manager::Chunk;
+
+    #[test]
+    fn hits_blocks_along_each_axis() {
+        let wall = |position: Position| position == Position::new(3, 0, 0);
+        let hit = raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::X, 10.0, wall).unwrap();
+        assert_eq!(hit.position, Position::new(3, 0, 0));
+        assert_eq!(hit.side, Side::Left);
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+
+        let ceiling = |position: Position| position.y == 2;
+        let hit = raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::Y, 10.0, ceiling).unwrap();
+        assert_eq!(hit.side, Side::Bottom);
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+
+        let floor = |position: Position| position.y == -1;
+        let hit = raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::NEG_Y, 10.0, floor).unwrap();
+        assert_eq!(hit.position, Position::new(0, -1, 0));
+        assert_eq!(hit.side, Side::Top);
+
+        let back = |position: Position| position.z == -2;
+        let hit = raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::NEG_Z, 10.0, back).unwrap();
+        assert_eq!(hit.side, Side::Back);
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn misses_blocks_out_of_reach() {
+        let wall = |position: Position| position.x == 6;
+        assert!(raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::X, 5.0, wall).is_none());
+        assert!(raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::X, 6.0, wall).is_some());
+    }
+
+    #[test]
+    fn ignores_the_block_it_starts_in() {
+        let everything = |_: Position| true;
+        let hit = raycast(Vec3::new(0.5, 0.5, 0.5), Vec3::NEG_X, 5.0, everything).unwrap();
+        assert_eq!(hit.position, Position::new(-1, 0, 0));
+        assert_eq!(hit.side, Side::Right);
+    }
+
+    #[test]
+    fn follows_diagonal_rays() {
+        let direction = Vec3::new(1.0, 1.0, 0.0);
+        let target = Position::new(3, 3, 0);
+        let hit = raycast(Vec3::new(0.5, 0.25, 0.5), direction, 10.0, |position| position == target).unwrap();
+        assert_eq!(hit.position, target);
+        // the ray reaches x = 3 first, so it enters the block from below
+        assert_eq!(hit.side, Side::Bottom);
+        assert!((hit.distance - 2.75 * 2f32.sqrt()).abs() < 1e-4);
+
+        // the ray stops at whichever of two blocks it reaches first
+        let blocked = |position: Position| position == Position::new(1, 0, 0) || position == Position::new(0, 1, 0);
+        let hit = raycast(Vec3::new(0.5, 0.75, 0.5), direction, 10.0, blocked).unwrap();
+        assert_eq!(hit.position, Position::new(0, 1, 0));
+    }
+
+    #[test]
+    fn crosses_chunk_borders() {
+        let stone = BlockType::from_id(3);
+        let mut chunks = ChunkManager::default();
+        for x in [-1, 0] {
+            let position = Position::new(x, 0, 0);
+            chunks.chunks.insert(position, Chunk::new(ChunkManager::chunk_origin(position).into(), BlockType::AIR));
+        }
+        chunks.set_block(Position::new(-2, 64, 5), stone);
+
+        let is_hit = |position: Position| chunks.get_block(position).is_some_and(|block| block != BlockType::AIR);
+        let hit = raycast(Vec3::new(1.5, 64.5, 5.5), Vec3::NEG_X, 5.0, is_hit).unwrap();
+        assert_eq!(hit.position, Position::new(-2, 64, 5));
+        assert_eq!(hit.side, Side::Right);
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn stops_at_plants_but_not_at_water() {
+        let registry = BlockRegistry::from_assets();
+        let grass = registry.by_name("tall_grass").unwrap();
+        let water = registry.by_name("water").unwrap();
+        let stone = registry.by_name("stone").unwrap();
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(Position::new(0, 0, 0), Chunk::new(Vec3::ZERO, BlockType::AIR));
+        chunks.set_block(Position::new(2, 64, 5), water);
+        chunks.set_block(Position::new(3, 64, 5), grass);
+        chunks.set_block(Position::new(4, 64, 5), stone);
+
+        let is_hit = |position: Position| chunks.get_block(position).is_some_and(|block| registry.is_targetable(block));
+        let hit = raycast(Vec3::new(0.5, 64.5, 5.5), Vec3::X, 5.0, is_hit).unwrap();
+        assert_eq!(hit.position, Position::new(3, 64, 5));
+        assert_eq!(hit.side, Side::Left);
+    }
+}
@@ -1,12 +1,455 @@
-#[rustfmt::skip]
-#[allow(dead_code)]
-pub enum BlockType {
-    Dirt,
-    Grass,
-    Stone,
-    Wood,
-    Leaves,
-    Water,
-    Sand,
-    Air,
-}
\ No newline at end of file
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use bevy::{asset::io::file::FileAssetReader, prelude::*};
+use bevy_meshem::prelude::*;
+use serde::Deserialize;
+
+use crate::array_texture_material::ATTRIBUTE_TEXTURE_LAYER;
+use crate::chunk_mesher::Side;
+use crate::lighting::MAX_LIGHT;
+use crate::load_texture_atlas::{TextureArray, TextureAtlas};
+
+/// the file the blocks are defined in, relative to the assets directory
+const BLOCKS_FILE: &str = "blocks.ron";
+
+/// A kind of block, identified by its id in the [`BlockRegistry`]. Everything else about the
+/// block is looked up in its [`BlockDefinition`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BlockType(u16);
+
+impl BlockType {
+    /// the empty block, always id 0
+    pub const AIR: BlockType = BlockType(0);
+
+    /// the numeric id of the block type, air is always 0
+    pub fn id(self) -> u16 {
+        self.0
+    }
+
+    pub fn from_id(id: u16) -> BlockType {
+        BlockType(id)
+    }
+}
+
+/// Everything about a block type, as written in the blocks file.
+#[derive(Deserialize, Debug)]
+pub struct BlockDefinition {
+    pub id: u16,
+    pub name: String,
+    /// the textures of the faces of the block. Blocks without textures are never drawn
+    #[serde(default)]
+    pub textures: Option<BlockTextures>,
+    /// whether blocks behind this one can be seen through it
+    pub transparent: bool,
+    /// how the faces of the block are drawn, [`RenderMode::Opaque`] if it is left out
+    #[serde(default)]
+    pub render: RenderMode,
+    /// the shape the block is drawn as, [`BlockShape::Cube`] if it is left out
+    #[serde(default)]
+    pub shape: BlockShape,
+    /// whether the block stops entities from moving through it
+    pub solid: bool,
+    /// whether the block flows into the empty blocks around it, see
+    /// [`FluidsPlugin`](crate::fluids::FluidsPlugin)
+    #[serde(default)]
+    pub fluid: bool,
+    /// whether the block falls down when there is nothing solid below it, see
+    /// [`FallingBlocksPlugin`](crate::falling_blocks::FallingBlocksPlugin)
+    #[serde(default)]
+    pub gravity: bool,
+    /// how much light the block gives off, from 0 to 15
+    #[serde(default)]
+    pub light: u8,
+    /// roughly how many seconds it takes to break the block by hand
+    pub hardness: f32,
+    /// how many of the block fit in one inventory slot
+    #[serde(default = "default_max_stack")]
+    pub max_stack: u16,
+}
+
+fn default_max_stack() -> u16 {
+    64
+}
+
+/// How the faces of a block are drawn. The faces of a chunk are split into one mesh for each
+/// render mode, each drawn with its own [`AlphaMode`].
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum RenderMode {
+    /// every pixel of the texture is drawn
+    #[default]
+    Opaque,
+    /// pixels of the texture that are more than half see-through are left out, like the gaps
+    /// between leaves
+    Cutout,
+    /// the texture is blended with what is behind it, like water and glass
+    Translucent,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 3] = [RenderMode::Opaque, RenderMode::Cutout, RenderMode::Translucent];
+
+    /// the alpha mode the faces are drawn with
+    pub fn alpha_mode(self) -> AlphaMode {
+        match self {
+            RenderMode::Opaque => AlphaMode::Opaque,
+            RenderMode::Cutout => AlphaMode::Mask(0.5),
+            RenderMode::Translucent => AlphaMode::Blend,
+        }
+    }
+}
+
+/// The shape of the mesh of a block.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BlockShape {
+    /// a whole block, with faces that are hidden behind the blocks next to them
+    #[default]
+    Cube,
+    /// two planes crossing diagonally through the block, showing the texture of its sides from
+    /// every direction, like a plant
+    Cross,
+}
+
+/// The name of the texture in the [`TextureAtlas`] shown on each face of a block. A face takes
+/// its own texture if it has one, then the `sides` texture if it is one of the four faces around
+/// the block, and `all` otherwise.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BlockTextures {
+    pub all: Option<String>,
+    pub sides: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+    pub forward: Option<String>,
+    pub back: Option<String>,
+}
+
+impl BlockTextures {
+    /// the texture of the `side` face, or `None` if the textures don't cover it
+    pub fn texture(&self, side: Side) -> Option<&str> {
+        let (own, around) = match side {
+            Side::Top => (&self.top, &None),
+            Side::Bottom => (&self.bottom, &None),
+            Side::Left => (&self.left, &self.sides),
+            Side::Right => (&self.right, &self.sides),
+            Side::Forward => (&self.forward, &self.sides),
+            Side::Back => (&self.back, &self.sides),
+        };
+        own.as_deref().or(around.as_deref()).or(self.all.as_deref())
+    }
+}
+
+/// Where the texture of a block face lies in the [`TextureArray`].
+#[derive(Clone, Copy, Debug)]
+pub struct FaceTexture {
+    /// the layer of the texture array shown on the face
+    pub layer: u32,
+}
+
+/// Every block type, loaded from [`BLOCKS_FILE`] in the assets directory, together with where
+/// their textures lie in the [`TextureAtlas`] and [`TextureArray`], and the meshes `bevy_meshem` builds each block type
+/// out of. Cloning the registry is cheap, so it can be handed to meshing tasks.
+#[derive(Resource, Clone)]
+pub struct BlockRegistry {
+    blocks: Arc<Blocks>,
+}
+
+struct Blocks {
+    /// indexed by block id
+    definitions: Vec<BlockDefinition>,
+    /// the atlas tile of each face of every block type that has textures, indexed by block id
+    /// and then by [`Side`]
+    tiles: Vec<Option<[(u32, u32); 6]>>,
+    /// the texture array layer of each face of every block type that has textures, indexed like
+    /// `tiles`
+    layers: Vec<Option<[u32; 6]>>,
+    /// the mesh of every block type that has textures, indexed by block id
+    meshes: Vec<Option<Mesh>>,
+    by_name: HashMap<String, BlockType>,
+}
+
+impl FromWorld for BlockRegistry {
+    fn from_world(world: &mut World) -> Self {
+        let atlas = world
+            .get_resource::<TextureAtlas>()
+            .expect("the LoadTextureAtlasPlugin has to be added before the block registry is created");
+        let array = world
+            .get_resource::<TextureArray>()
+            .expect("the LoadTextureAtlasPlugin has to be added before the block registry is created");
+        let path = FileAssetReader::get_base_path().join("assets").join(BLOCKS_FILE);
+        match Self::load(&path, atlas, array) {
+            Ok(registry) => registry,
+            Err(error) => panic!("failed to load the blocks from {error}"),
+        }
+    }
+}
+
+impl BlockRegistry {
+    /// read the block definitions from the RON file at `path`. Errors name the file they came
+    /// from
+    pub fn load(path: &Path, atlas: &TextureAtlas, array: &TextureArray) -> io::Result<Self> {
+        fs::read_to_string(path)
+            .and_then(|text| Self::from_ron(&text, atlas, array))
+            .map_err(|error| io::Error::new(error.kind(), format!("{}: {error}", path.display())))
+    }
+
+    /// Build the registry from a RON list of [`BlockDefinition`]s. The ids have to count up
+    /// from 0, which has to be an air block without textures, every name has to be unique, only
+    /// transparent blocks can be drawn see-through, crosses have to be, fluids can't be solid, and
+    /// every textured block needs a texture for each of its faces that is in both `atlas` and
+    /// `array`.
+    pub fn from_ron(text: &str, atlas: &TextureAtlas, array: &TextureArray) -> io::Result<Self> {
+        let mut definitions: Vec<BlockDefinition> = ron::from_str(text).map_err(|error| invalid_data(error.to_string()))?;
+        definitions.sort_by_key(|definition| definition.id);
+
+        let mut by_name = HashMap::new();
+        for (i, definition) in definitions.iter().enumerate() {
+            if definition.id as usize != i {
+                return Err(invalid_data(format!("block ids have to count up from 0 without gaps, expected id {i} but found {}", definition.id)));
+            }
+            if by_name.insert(definition.name.clone(), BlockType(definition.id)).is_some() {
+                return Err(invalid_data(format!("there is more than one block called {}", definition.name)));
+            }
+            if definition.light > MAX_LIGHT {
+                return Err(invalid_data(format!("block {} gives off more than {MAX_LIGHT} light", definition.name)));
+            }
+            if definition.render != RenderMode::Opaque && !definition.transparent {
+                return Err(invalid_data(format!("block {} is drawn see-through but isn't transparent", definition.name)));
+            }
+            if definition.shape == BlockShape::Cross && definition.render == RenderMode::Opaque {
+                return Err(invalid_data(format!("block {} is a cross but isn't drawn see-through", definition.name)));
+            }
+            if definition.fluid && definition.solid {
+                return Err(invalid_data(format!("block {} is a fluid but is solid", definition.name)));
+            }
+            if definition.max_stack == 0 {
+                return Err(invalid_data(format!("block {} has a max_stack of 0", definition.name)));
+            }
+        }
+        match definitions.first() {
+            Some(air) if air.textures.is_none() && air.transparent => {}
+            _ => return Err(invalid_data("block 0 has to be an invisible, transparent air block")),
+        }
+
+        let mut tiles = Vec::with_capacity(definitions.len());
+        let mut layers = Vec::with_capacity(definitions.len());
+        for definition in &definitions {
+            let Some(textures) = &definition.textures else {
+                tiles.push(None);
+                layers.push(None);
+                continue;
+            };
+            let mut faces = [(0, 0); 6];
+            let mut face_layers = [0; 6];
+            for side in Side::ALL {
+                let name = textures.texture(side).ok_or_else(|| {
+                    invalid_data(format!("block {} has no texture for its {side:?} face", definition.name))
+                })?;
+                let missing = || invalid_data(format!("block {} uses the missing texture {name}", definition.name));
+                faces[side as usize] = atlas.tile(name).ok_or_else(missing)?;
+                face_layers[side as usize] = array.layer(name).ok_or_else(missing)?;
+            }
+            tiles.push(Some(faces));
+            layers.push(Some(face_layers));
+        }
+
+        let layout = atlas.layout;
+        let meshes = tiles
+            .iter()
+            .zip(&layers)
+            .map(|(faces, face_layers)| {
+                let (faces, face_layers) = (faces.as_ref()?, face_layers.as_ref()?);
+                let order = [Top, Bottom, Right, Left, Back, Forward];
+                let mut mesh = generate_voxel_mesh(
+                    [1.0, 1.0, 1.0],
+                    [layout.columns, layout.rows],
+                    order.map(|face| {
+                        let (column, row) = faces[Side::from_face(face) as usize];
+                        (face, [column, row])
+                    }),
+                    [0.5, 0.5, 0.5],
+                    layout.padding,
+                    Some(0.8),
+                    1.0,
+                );
+                // the mesh has four vertices per face, in the order the faces were given
+                let vertex_layers: Vec<u32> = order
+                    .iter()
+                    .flat_map(|face| [face_layers[Side::from_face(*face) as usize]; 4])
+                    .collect();
+                mesh.insert_attribute(ATTRIBUTE_TEXTURE_LAYER, vertex_layers);
+                // filled in with the light around each face once the chunk is meshed
+                mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![[1.0; 4]; order.len() * 4]);
+                Some(mesh)
+            })
+            .collect();
+
+        Ok(Self {
+            blocks: Arc::new(Blocks {
+                definitions,
+                tiles,
+                layers,
+                meshes,
+                by_name,
+            }),
+        })
+    }
+
+    /// # Panics
+    /// if `block` isn't in the registry, see [`BlockRegistry::contains`]
+    pub fn get(&self, block: BlockType) -> &BlockDefinition {
+        &self.blocks.definitions[block.0 as usize]
+    }
+
+    pub fn contains(&self, block: BlockType) -> bool {
+        (block.0 as usize) < self.blocks.definitions.len()
+    }
+
+    /// the block type called `name` in the blocks file
+    pub fn by_name(&self, name: &str) -> Option<BlockType> {
+        self.blocks.by_name.get(name).copied()
+    }
+
+    /// The atlas tile shown on the `side` face of `block`.
+    ///
+    /// # Panics
+    /// if `block` isn't drawn, see [`BlockRegistry::is_visible`]
+    pub fn face_tile(&self, block: BlockType, side: Side) -> (u32, u32) {
+        let faces = self.blocks.tiles[block.0 as usize].expect("only visible blocks have faces");
+        faces[side as usize]
+    }
+
+    /// The texture shown on the `side` face of `block`.
+    ///
+    /// # Panics
+    /// if `block` isn't drawn, see [`BlockRegistry::is_visible`]
+    pub fn face_texture(&self, block: BlockType, side: Side) -> FaceTexture {
+        let layers = self.blocks.layers[block.0 as usize].expect("only visible blocks have faces");
+        FaceTexture {
+            layer: layers[side as usize],
+        }
+    }
+
+    /// whether `block` is drawn at all
+    pub fn is_visible(&self, block: BlockType) -> bool {
+        self.get(block).textures.is_some()
+    }
+
+    /// whether rays stop at `block`, so it can be looked at, broken and built against, see
+    /// [`target_block`](crate::block_targeting::target_block)
+    pub fn is_targetable(&self, block: BlockType) -> bool {
+        self.is_visible(block) && !self.is_fluid(block)
+    }
+
+    /// whether `block` flows
+    pub fn is_fluid(&self, block: BlockType) -> bool {
+        self.get(block).fluid
+    }
+
+    /// the shape `block` is drawn as
+    pub fn shape(&self, block: BlockType) -> BlockShape {
+        self.get(block).shape
+    }
+
+    /// whether `block` falls when nothing holds it up
+    pub fn has_gravity(&self, block: BlockType) -> bool {
+        self.get(block).gravity
+    }
+
+    /// how the faces of `block` are drawn
+    pub fn render_mode(&self, block: BlockType) -> RenderMode {
+        self.get(block).render
+    }
+
+    /// Whether the face of `block` that touches `neighbour` should be drawn. Faces are hidden
+    /// behind opaque blocks and between two blocks of the same transparent type, so the inside
+    /// of a lake is not meshed and a wall of glass doesn't show the panes between its blocks. A
+    /// missing neighbour (outside of the world, or not loaded yet) never hides a face.
+    pub fn shows_face_towards(&self, block: BlockType, neighbour: Option<BlockType>) -> bool {
+        match neighbour {
+            None => true,
+            Some(neighbour) => self.get(neighbour).transparent && neighbour != block,
+        }
+    }
+}
+
+#[cfg(test)]
+impl BlockRegistry {
+    /// the blocks and textures in the assets directory, for tests that need real block types
+    pub fn from_assets() -> Self {
+        use crate::load_texture_atlas::{read_block_textures, BLOCK_TEXTURES_DIRECTORY};
+
+        let assets = FileAssetReader::get_base_path().join("assets");
+        let (atlas, array) = read_block_textures(&assets.join(BLOCK_TEXTURES_DIRECTORY)).unwrap();
+        Self::load(&assets.join(BLOCKS_FILE), &atlas, &array).unwrap()
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Lets `bevy_meshem` mesh chunks out of the cube meshes built for every block when the
+/// registry is loaded.
+impl VoxelRegistry for BlockRegistry {
+    /// the id of a [`BlockType`]
+    type Voxel = u16;
+    /// the cube mesh of the block, or nothing for blocks that aren't drawn
+    fn get_mesh(&self, voxel: &Self::Voxel) -> VoxelMesh<&Mesh> {
+        match &self.blocks.meshes[*voxel as usize] {
+            Some(mesh) => VoxelMesh::NormalCube(mesh),
+            None => VoxelMesh::Null,
+        }
+    }
+    /// whether the block hides the faces next to it, see [`BlockDefinition::transparent`]
+    fn is_covering(&self, voxel: &Self::Voxel, _side: Face) -> bool {
+        !self.get(BlockType(*voxel)).transparent
+    }
+    /// every block mesh is a unit cube centred in its block
+    fn get_center(&self) -> [f32; 3] {
+        [0.5, 0.5, 0.5]
+    }
+    fn get_voxel_dimensions(&self) -> [f32; 3] {
+        [1.0, 1.0, 1.0]
+    }
+    /// The vertex attributes of the block meshes that are copied into the chunk meshes: the
+    /// [`TextureArray`] layer of each face for the
+    /// [`ArrayTextureMaterial`](crate::array_texture_material::ArrayTextureMaterial), and a colour
+    /// that meshing fills in with the light of each vertex.
+    fn all_attributes(&self) -> Vec<bevy::render::mesh::MeshVertexAttribute> {
+        vec![
+            Mesh::ATTRIBUTE_POSITION,
+            Mesh::ATTRIBUTE_UV_0,
+            Mesh::ATTRIBUTE_NORMAL,
+            ATTRIBUTE_TEXTURE_LAYER,
+            Mesh::ATTRIBUTE_COLOR,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_texture_atlas::{read_block_textures, BLOCK_TEXTURES_DIRECTORY};
+
+    #[test]
+    fn errors_loading_the_blocks_name_the_file() {
+        let assets = FileAssetReader::get_base_path().join("assets");
+        let (atlas, array) = read_block_textures(&assets.join(BLOCK_TEXTURES_DIRECTORY)).unwrap();
+        let path = std::env::temp_dir().join(format!("blocks-{}.ron", std::process::id()));
+
+        let missing = BlockRegistry::load(&path, &atlas, &array).err().unwrap();
+        assert!(missing.to_string().starts_with(&path.display().to_string()), "{missing}");
+
+        fs::write(&path, "[(id: 0, name: \"air\"").unwrap();
+        let malformed = BlockRegistry::load(&path, &atlas, &array).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(malformed.kind(), io::ErrorKind::InvalidData);
+        assert!(malformed.to_string().starts_with(&path.display().to_string()), "{malformed}");
+    }
+}
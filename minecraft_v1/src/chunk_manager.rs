@@ -1,22 +1,281 @@
 use bevy::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::ops::Add;
 
 use crate::block_types::BlockType;
+use crate::chunk_storage::PalettedStorage;
+use crate::lighting::{Light, LightKind};
+
+/// size of a chunk along the x axis
+pub const CHUNK_WIDTH: usize = 16;
+/// size of a chunk along the y axis
+pub const CHUNK_HEIGHT: usize = 256;
+/// size of a chunk along the z axis
+pub const CHUNK_DEPTH: usize = 16;
+/// the number of blocks in a chunk
+pub const CHUNK_VOLUME: usize = CHUNK_WIDTH * CHUNK_HEIGHT * CHUNK_DEPTH;
 
 pub struct ChunkManagerPlugin;
 
 impl Plugin for ChunkManagerPlugin {
     fn build(&self, app: &mut App) {
         app
-            .insert_resource(ChunkManager {
-                chunks: HashMap::default(),
-            });
+            .init_resource::<ChunkManager>()
+            .add_event::<RegenerateMesh>()
+            .add_systems(PostUpdate, send_remesh_events);
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Default)]
 pub struct ChunkManager {
+    /// the loaded chunks, keyed by chunk coordinate (the chunk at world x 16..32 has x 1)
     pub chunks: HashMap<Position, Chunk>,
+    /// block edits made since the last [`RegenerateMesh`] events were sent
+    edits: Vec<BlockEdit>,
+    /// the chunk coordinates of the chunks whose light changed since the last
+    /// [`RegenerateMesh`] events were sent
+    relit: HashSet<Position>,
+}
+
+/// A single block that was changed through [`ChunkManager::set_block`], or whose flow level
+/// was changed through [`ChunkManager::set_flow_level`], in which case `previous` and `block`
+/// are the same.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockEdit {
+    /// world position of the block
+    pub position: Position,
+    pub previous: BlockType,
+    pub block: BlockType,
+}
+
+/// Sent when the mesh of `chunk` has to be brought up to date with the blocks and light in it.
+/// `edits` holds every edit this frame that can change the mesh, which includes edits in
+/// neighbouring chunks that lie right on the border. Without any edits the whole mesh is
+/// rebuilt, which is the case when only the light in the chunk changed.
+#[derive(Event)]
+pub struct RegenerateMesh {
+    pub chunk: Position,
+    pub edits: Vec<BlockEdit>,
+}
+
+impl ChunkManager {
+    /// the block at world position `world`, or `None` if its chunk isn't loaded or `world` lies
+    /// above or below the world
+    pub fn get_block(&self, world: Position) -> Option<BlockType> {
+        self.chunks.get(&Self::chunk_coordinate(world))?.get(Self::local_position(world))
+    }
+
+    /// Replace the block at world position `world`, returning the block that was there before.
+    /// Returns `None` and does nothing if the chunk isn't loaded or `world` lies above or below
+    /// the world. The meshes showing the block are regenerated at the end of the frame.
+    pub fn set_block(&mut self, world: Position, block: BlockType) -> Option<BlockType> {
+        let chunk = self.chunks.get_mut(&Self::chunk_coordinate(world))?;
+        let local = Self::local_position(world);
+        let previous = chunk.get(local)?;
+        if previous != block {
+            chunk.set(local, block);
+            chunk.dirty = true;
+            self.edits.push(BlockEdit {
+                position: world,
+                previous,
+                block,
+            });
+        }
+        Some(previous)
+    }
+
+    /// the level of the flowing fluid at world position `world`, or `None` if the block there
+    /// isn't flowing, its chunk isn't loaded or `world` lies above or below the world
+    pub fn flow_level(&self, world: Position) -> Option<u8> {
+        self.chunks.get(&Self::chunk_coordinate(world))?.flow_level(Self::local_position(world))
+    }
+
+    /// Change the level of the fluid at world position `world`, with `None` for a source, if its
+    /// chunk is loaded. The meshes showing the block are regenerated at the end of the frame.
+    pub fn set_flow_level(&mut self, world: Position, level: Option<u8>) {
+        let Some(chunk) = self.chunks.get_mut(&Self::chunk_coordinate(world)) else {
+            return;
+        };
+        let local = Self::local_position(world);
+        let Some(block) = chunk.get(local) else {
+            return;
+        };
+        if chunk.flow_level(local) != level {
+            chunk.set_flow_level(local, level);
+            chunk.dirty = true;
+            self.edits.push(BlockEdit {
+                position: world,
+                previous: block,
+                block,
+            });
+        }
+    }
+
+    /// the block edits made this frame, oldest first
+    pub fn edits(&self) -> &[BlockEdit] {
+        &self.edits
+    }
+
+    /// the light at world position `world`, or `None` if its chunk isn't loaded or `world` lies
+    /// above or below the world
+    pub fn get_light(&self, world: Position) -> Option<Light> {
+        self.chunks.get(&Self::chunk_coordinate(world))?.light(Self::local_position(world))
+    }
+
+    /// Change one kind of light at world position `world`, if its chunk is loaded. The meshes of
+    /// the chunk are regenerated at the end of the frame.
+    pub fn set_light(&mut self, world: Position, kind: LightKind, level: u8) {
+        let chunk_coordinate = Self::chunk_coordinate(world);
+        let Some(chunk) = self.chunks.get_mut(&chunk_coordinate) else {
+            return;
+        };
+        let local = Self::local_position(world);
+        if Chunk::contains(local) {
+            chunk.set_light(local, kind, level);
+            self.relit.insert(chunk_coordinate);
+        }
+    }
+
+    /// Replace the block at world position `world` with air, see [`ChunkManager::set_block`].
+    pub fn remove_block(&mut self, world: Position) -> Option<BlockType> {
+        self.set_block(world, BlockType::AIR)
+    }
+
+    /// the coordinate of the chunk containing the block at world position `world`
+    pub fn chunk_coordinate(world: Position) -> Position {
+        Position::new(
+            world.x.div_euclid(CHUNK_WIDTH as isize),
+            0,
+            world.z.div_euclid(CHUNK_DEPTH as isize),
+        )
+    }
+
+    /// the world position of the first block of the chunk at chunk coordinate `chunk`
+    pub fn chunk_origin(chunk: Position) -> Position {
+        Position::new(chunk.x * CHUNK_WIDTH as isize, 0, chunk.z * CHUNK_DEPTH as isize)
+    }
+
+    /// the position of the block at world position `world` relative to its chunk
+    pub fn local_position(world: Position) -> Position {
+        Position::new(
+            world.x.rem_euclid(CHUNK_WIDTH as isize),
+            world.y,
+            world.z.rem_euclid(CHUNK_DEPTH as isize),
+        )
+    }
+}
+
+/// Turn the block edits and light changes of this frame into one [`RegenerateMesh`] event per
+/// affected chunk.
+pub fn send_remesh_events(mut chunks: ResMut<ChunkManager>, mut events: EventWriter<RegenerateMesh>) {
+    let mut affected: HashMap<Position, Vec<BlockEdit>> = HashMap::new();
+    let edits = std::mem::take(&mut chunks.edits);
+    for edit in edits {
+        let chunk = ChunkManager::chunk_coordinate(edit.position);
+        let local = ChunkManager::local_position(edit.position);
+        affected.entry(chunk).or_default().push(edit);
+
+        // the faces of blocks across the border depend on this block too
+        let mut neighbours = Vec::new();
+        if local.x == 0 {
+            neighbours.push(Position::new(-1, 0, 0));
+        }
+        if local.x == CHUNK_WIDTH as isize - 1 {
+            neighbours.push(Position::new(1, 0, 0));
+        }
+        if local.z == 0 {
+            neighbours.push(Position::new(0, 0, -1));
+        }
+        if local.z == CHUNK_DEPTH as isize - 1 {
+            neighbours.push(Position::new(0, 0, 1));
+        }
+        // and the corners of faces across a chunk corner are occluded by it
+        if let [x, z] = neighbours[..] {
+            neighbours.push(x + z);
+        }
+        for offset in neighbours {
+            if chunks.chunks.contains_key(&(chunk + offset)) {
+                affected.entry(chunk + offset).or_default().push(edit);
+            }
+        }
+    }
+    let relit = std::mem::take(&mut chunks.relit);
+    for chunk in relit {
+        affected.entry(chunk).or_default();
+    }
+    for (chunk, edits) in affected {
+        events.send(RegenerateMesh { chunk, edits });
+    }
+}
+
+/// A chunk together with the eight chunks around it, so blocks just across the chunk border can
+/// be looked up by the chunk's local coordinates.
+pub struct ChunkNeighbourhood<'a> {
+    /// the chunks indexed by `[x + 1][z + 1]` of their offset from the centre chunk
+    chunks: [[Option<&'a Chunk>; 3]; 3],
+}
+
+impl<'a> ChunkNeighbourhood<'a> {
+    /// the neighbourhood of the chunk at chunk coordinate `position`, or `None` if that chunk
+    /// isn't loaded
+    pub fn new(manager: &'a ChunkManager, position: Position) -> Option<Self> {
+        manager.chunks.get(&position)?;
+        let mut chunks = [[None; 3]; 3];
+        for (x, row) in chunks.iter_mut().enumerate() {
+            for (z, chunk) in row.iter_mut().enumerate() {
+                let offset = Position::new(x as isize - 1, 0, z as isize - 1);
+                *chunk = manager.chunks.get(&(position + offset));
+            }
+        }
+        Some(Self { chunks })
+    }
+
+    /// the chunk in the middle of the neighbourhood
+    pub fn centre(&self) -> &'a Chunk {
+        self.chunks[1][1].expect("the centre of a neighbourhood is always loaded")
+    }
+
+    /// the chunk `x` and `z` chunks away from the centre, both in the range -1..=1
+    pub fn chunk(&self, x: isize, z: isize) -> Option<&'a Chunk> {
+        self.chunks[(x + 1) as usize][(z + 1) as usize]
+    }
+
+    /// copy the chunks of the neighbourhood, so they can be sent to another thread
+    pub fn snapshot(&self) -> NeighbourhoodSnapshot {
+        NeighbourhoodSnapshot {
+            chunks: self.chunks.map(|row| row.map(|chunk| chunk.cloned())),
+        }
+    }
+
+    /// the block at `local`, relative to the centre chunk. `local` may lie up to a chunk outside
+    /// of the centre chunk on the x and z axes
+    pub fn get(&self, local: Position) -> Option<BlockType> {
+        let (chunk, local) = self.locate(local)?;
+        chunk.get(local)
+    }
+
+    /// the light at `local`, relative to the centre chunk like [`ChunkNeighbourhood::get`]
+    pub fn light(&self, local: Position) -> Option<Light> {
+        let (chunk, local) = self.locate(local)?;
+        chunk.light(local)
+    }
+
+    /// the flow level at `local`, relative to the centre chunk like [`ChunkNeighbourhood::get`]
+    pub fn flow_level(&self, local: Position) -> Option<u8> {
+        let (chunk, local) = self.locate(local)?;
+        chunk.flow_level(local)
+    }
+
+    /// the chunk `local` lies in, and where it lies in that chunk
+    fn locate(&self, local: Position) -> Option<(&'a Chunk, Position)> {
+        let x = local.x.div_euclid(CHUNK_WIDTH as isize) + 1;
+        let z = local.z.div_euclid(CHUNK_DEPTH as isize) + 1;
+        if !(0..3).contains(&x) || !(0..3).contains(&z) {
+            return None;
+        }
+        Some((self.chunks[x as usize][z as usize]?, ChunkManager::local_position(local)))
+    }
 }
 
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
@@ -32,9 +291,17 @@ impl Position {
     }
 }
 
-impl Into<Vec3> for Position {
-    fn into(self) -> Vec3 {
-        Vec3::new(self.x as f32, self.y as f32, self.z as f32)
+impl Add for Position {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl From<Position> for Vec3 {
+    fn from(pos: Position) -> Self {
+        Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32)
     }
 }
 
@@ -48,22 +315,233 @@ impl From<Vec3> for Position {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Chunk {
     /// position of the chunk based on the top left corner block
     pub position: Vec3,
-    pub blocks: HashMap<Position, Block>,
-}
-
-#[derive(Component)]
-pub struct Block {
-    /// position of the block relative to the chunk
-    pub local_position: Vec3, 
-    /// position of the block relative to the world
-    pub absolute_position: Vec3,
-    /// whether the block is visible or not
-    pub is_visible: bool,
-    /// the type of the block
-    pub block_type: BlockType,
-    /// whether the block is transparent or not. Used for rendering
-    pub is_transparent: bool,
-}
\ No newline at end of file
+    /// the blocks of the chunk, indexed by [`Chunk::index`]
+    blocks: PalettedStorage,
+    /// the light level of every block, indexed like `blocks`, with the sky light in the high
+    /// four bits and the block light in the low four. Light isn't saved, it is worked out again
+    /// whenever the chunk is loaded
+    light: Vec<u8>,
+    /// the level of every flowing fluid block, keyed by its index in `blocks`. Fluid blocks that
+    /// aren't in here are sources, and every block loses its level when it is replaced
+    flow_levels: HashMap<usize, u8>,
+    /// whether blocks were edited through [`ChunkManager::set_block`] since the chunk was last
+    /// saved. Freshly generated chunks are clean, since they can be generated again
+    dirty: bool,
+}
+
+impl Chunk {
+    /// create a chunk at `position` where every block is `fill`
+    pub fn new(position: Vec3, fill: BlockType) -> Self {
+        Self {
+            position,
+            blocks: PalettedStorage::new(CHUNK_VOLUME, fill),
+            light: vec![0; CHUNK_VOLUME],
+            flow_levels: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// whether the chunk has edits that haven't been saved yet
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// forget about the unsaved edits, after the chunk was saved
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+    }
+
+    /// write the blocks of the chunk to `out`, followed by the number of flowing blocks and the
+    /// index and level of each of them
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        self.blocks.write_to(out)?;
+        let mut flow_levels: Vec<(&usize, &u8)> = self.flow_levels.iter().collect();
+        flow_levels.sort();
+        out.write_all(&(flow_levels.len() as u32).to_le_bytes())?;
+        for (index, level) in flow_levels {
+            out.write_all(&(*index as u32).to_le_bytes())?;
+            out.write_all(&[*level])?;
+        }
+        Ok(())
+    }
+
+    /// read the blocks of a chunk at `position` written by [`Chunk::write_to`]
+    pub fn read_from(position: Vec3, input: &mut impl Read) -> io::Result<Self> {
+        let blocks = PalettedStorage::read_from(input, CHUNK_VOLUME)?;
+        let mut flow_levels = HashMap::new();
+        let mut count = [0; 4];
+        input.read_exact(&mut count)?;
+        for _ in 0..u32::from_le_bytes(count) {
+            let mut entry = [0; 5];
+            input.read_exact(&mut entry)?;
+            let index = u32::from_le_bytes(entry[..4].try_into().unwrap()) as usize;
+            if index >= CHUNK_VOLUME {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "flowing block out of chunk bounds"));
+            }
+            flow_levels.insert(index, entry[4]);
+        }
+        Ok(Self {
+            position,
+            blocks,
+            light: vec![0; CHUNK_VOLUME],
+            flow_levels,
+            dirty: false,
+        })
+    }
+
+    /// whether `local` lies inside the bounds of a chunk
+    pub fn contains(local: Position) -> bool {
+        (0..CHUNK_WIDTH as isize).contains(&local.x)
+            && (0..CHUNK_HEIGHT as isize).contains(&local.y)
+            && (0..CHUNK_DEPTH as isize).contains(&local.z)
+    }
+
+    /// the index of the block at `local` in the block array. Blocks are laid out in y, z, x
+    /// order, the same order `bevy_meshem` expects its grids in.
+    pub fn index(local: Position) -> usize {
+        local.y as usize * CHUNK_WIDTH * CHUNK_DEPTH + local.z as usize * CHUNK_WIDTH + local.x as usize
+    }
+
+    /// the local position of the block at `index` in the block array
+    pub fn position_of(index: usize) -> Position {
+        Position::new(
+            (index % CHUNK_WIDTH) as isize,
+            (index / (CHUNK_WIDTH * CHUNK_DEPTH)) as isize,
+            (index / CHUNK_WIDTH % CHUNK_DEPTH) as isize,
+        )
+    }
+
+    /// the block at `local`, or `None` if `local` lies outside of the chunk
+    pub fn get(&self, local: Position) -> Option<BlockType> {
+        if !Self::contains(local) {
+            return None;
+        }
+        Some(self.blocks.get(Self::index(local)))
+    }
+
+    /// replace the block at `local`, which starts out as a source if it is a fluid
+    ///
+    /// # Panics
+    /// if `local` lies outside of the chunk
+    pub fn set(&mut self, local: Position, block: BlockType) {
+        assert!(Self::contains(local), "{local:?} is outside of the chunk");
+        let index = Self::index(local);
+        self.blocks.set(index, block);
+        self.flow_levels.remove(&index);
+    }
+
+    /// the level of the block at `local` if it is a flowing fluid, or `None` if it is a source,
+    /// any other block, or lies outside of the chunk
+    pub fn flow_level(&self, local: Position) -> Option<u8> {
+        if !Self::contains(local) {
+            return None;
+        }
+        self.flow_levels.get(&Self::index(local)).copied()
+    }
+
+    /// change the level of the fluid at `local`, with `None` for a source
+    ///
+    /// # Panics
+    /// if `local` lies outside of the chunk
+    pub fn set_flow_level(&mut self, local: Position, level: Option<u8>) {
+        assert!(Self::contains(local), "{local:?} is outside of the chunk");
+        let index = Self::index(local);
+        match level {
+            Some(level) => self.flow_levels.insert(index, level),
+            None => self.flow_levels.remove(&index),
+        };
+    }
+
+    /// the light at `local`, or `None` if `local` lies outside of the chunk
+    pub fn light(&self, local: Position) -> Option<Light> {
+        if !Self::contains(local) {
+            return None;
+        }
+        let packed = self.light[Self::index(local)];
+        Some(Light {
+            sky: packed >> 4,
+            block: packed & 0xf,
+        })
+    }
+
+    /// change one kind of light at `local`
+    ///
+    /// # Panics
+    /// if `local` lies outside of the chunk
+    pub fn set_light(&mut self, local: Position, kind: LightKind, level: u8) {
+        assert!(Self::contains(local), "{local:?} is outside of the chunk");
+        let packed = &mut self.light[Self::index(local)];
+        *packed = match kind {
+            LightKind::Sky => (*packed & 0xf) | level << 4,
+            LightKind::Block => (*packed & 0xf0) | level,
+        };
+    }
+
+    /// every block type that appears in the chunk, and possibly some that used to
+    pub fn palette(&self) -> &[BlockType] {
+        self.blocks.palette()
+    }
+
+    /// every block of the chunk together with its local position
+    pub fn iter(&self) -> impl Iterator<Item = (Position, BlockType)> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (Self::position_of(i), block))
+    }
+}
+
+/// An owned copy of the chunks in a [`ChunkNeighbourhood`].
+pub struct NeighbourhoodSnapshot {
+    chunks: [[Option<Chunk>; 3]; 3],
+}
+
+impl NeighbourhoodSnapshot {
+    pub fn neighbourhood(&self) -> ChunkNeighbourhood<'_> {
+        ChunkNeighbourhood {
+            chunks: self.chunks.each_ref().map(|row| row.each_ref().map(Option::as_ref)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a chunk of stone with a few other blocks in it, and the water at `water` flowing at
+    /// `level`
+    fn test_chunk(water: Position, level: Option<u8>) -> Chunk {
+        let mut chunk = Chunk::new(Vec3::ZERO, BlockType::from_id(3));
+        chunk.set(Position::new(0, 0, 0), BlockType::AIR);
+        chunk.set(Position::new(15, 255, 15), BlockType::from_id(5));
+        chunk.set(water, BlockType::from_id(6));
+        chunk.set_flow_level(water, level);
+        chunk
+    }
+
+    /// write `chunk` out and read it back in
+    fn round_trip(chunk: &Chunk) -> Chunk {
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        Chunk::read_from(chunk.position, &mut bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn paletted_chunks_round_trip() {
+        let chunk = test_chunk(Position::new(4, 60, 9), None);
+        assert_eq!(round_trip(&chunk), chunk);
+    }
+
+    #[test]
+    fn flow_levels_round_trip() {
+        let water = Position::new(4, 60, 9);
+        let chunk = test_chunk(water, Some(3));
+        let read = round_trip(&chunk);
+        assert_eq!(read.flow_level(water), Some(3));
+        assert_eq!(read, chunk);
+    }
+}
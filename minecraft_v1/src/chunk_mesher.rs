@@ -0,0 +1,967 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+};
+
+use bevy_meshem::prelude::{
+    introduce_adjacent_chunks, mesh_grid, update_mesh, Face, MeshMD, VoxelChange,
+};
+
+use crate::array_texture_material::ATTRIBUTE_TEXTURE_LAYER;
+use crate::block_types::{BlockRegistry, BlockShape, BlockType, FaceTexture, RenderMode};
+use crate::chunk_manager::{
+    BlockEdit, Chunk, ChunkManager, ChunkNeighbourhood, Position, CHUNK_DEPTH, CHUNK_HEIGHT,
+    CHUNK_WIDTH,
+};
+use crate::fluids::{fluid_level, surface_height};
+use crate::lighting::{Light, MAX_LIGHT};
+
+/// How the faces of a chunk are turned into quads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MeshingAlgorithm {
+    /// one quad for every visible block face
+    Culling,
+    /// visible faces of the same block type lying next to each other in the same plane are
+    /// merged into one larger quad, over which the block's texture repeats once per block
+    #[default]
+    Greedy,
+    /// `bevy_meshem`'s culling mesher. It keeps metadata about which quads belong to which block,
+    /// so block edits can be patched into the mesh instead of rebuilding it
+    Incremental,
+}
+
+/// How the mesh of a chunk is built.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MeshingOptions {
+    pub algorithm: MeshingAlgorithm,
+    /// darken the corners of faces that are tucked in next to other blocks
+    pub ambient_occlusion: bool,
+}
+
+/// The six faces of a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Top,
+    Bottom,
+    Forward,
+    Back,
+    Left,
+    Right,
+}
+
+impl Side {
+    pub const ALL: [Side; 6] = [
+        Side::Top,
+        Side::Bottom,
+        Side::Forward,
+        Side::Back,
+        Side::Left,
+        Side::Right,
+    ];
+
+    /// offset from a block to the neighbour this side is facing
+    pub fn offset(self) -> Position {
+        match self {
+            Side::Top => Position::new(0, 1, 0),
+            Side::Bottom => Position::new(0, -1, 0),
+            Side::Forward => Position::new(0, 0, -1),
+            Side::Back => Position::new(0, 0, 1),
+            Side::Left => Position::new(-1, 0, 0),
+            Side::Right => Position::new(1, 0, 0),
+        }
+    }
+
+    /// the side matching a `bevy_meshem` face
+    pub fn from_face(face: Face) -> Side {
+        match face {
+            Face::Top => Side::Top,
+            Face::Bottom => Side::Bottom,
+            Face::Right => Side::Right,
+            Face::Left => Side::Left,
+            Face::Back => Side::Back,
+            Face::Forward => Side::Forward,
+        }
+    }
+
+    pub fn normal(self) -> [f32; 3] {
+        let offset = self.offset();
+        [offset.x as f32, offset.y as f32, offset.z as f32]
+    }
+
+    /// the axis (0 = x, 1 = y, 2 = z) this side is facing along
+    fn axis(self) -> usize {
+        match self {
+            Side::Left | Side::Right => 0,
+            Side::Top | Side::Bottom => 1,
+            Side::Forward | Side::Back => 2,
+        }
+    }
+
+    /// the axes running along the texture's u and v directions of this side
+    fn texture_axes(self) -> (usize, usize) {
+        match self {
+            Side::Top | Side::Bottom => (0, 2),
+            Side::Left | Side::Right => (2, 1),
+            Side::Forward | Side::Back => (0, 1),
+        }
+    }
+
+    /// The corners of this side of a block spanning 0..1 on every axis. They are ordered bottom
+    /// left, bottom right, top right, top left as seen from outside the block, so the face winds
+    /// counter-clockwise towards its normal.
+    #[rustfmt::skip]
+    fn corners(self) -> [[f32; 3]; 4] {
+        match self {
+            // top      (+y)
+            Side::Top => [[0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+            // bottom   (-y)
+            Side::Bottom => [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]],
+            // right    (+x)
+            Side::Right => [[1.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0]],
+            // left     (-x)
+            Side::Left => [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]],
+            // back     (+z)
+            Side::Back => [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]],
+            // forward  (-z)
+            Side::Forward => [[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]],
+        }
+    }
+}
+
+/// The vertex colour of a face corner lit by `light` and occluded by `occlusion`. Chunk meshes
+/// don't have colours of their own, so the red channel holds the sky light, the green channel
+/// the block light and the blue channel the ambient occlusion, all scaled to 0..1.
+fn light_color(light: Light, occlusion: u8) -> [f32; 4] {
+    let max = MAX_LIGHT as f32;
+    [
+        light.sky as f32 / max,
+        light.block as f32 / max,
+        occlusion as f32 / MAX_AMBIENT_OCCLUSION as f32,
+        1.0,
+    ]
+}
+
+/// the light falling onto the `side` face of the block at `local`, which is the light of the
+/// block in front of it
+fn face_light(neighbourhood: &ChunkNeighbourhood, local: Position, side: Side) -> Light {
+    neighbourhood.light(local + side.offset()).unwrap_or(Light::DAYLIGHT)
+}
+
+/// the ambient occlusion of a face corner with nothing around it
+pub const MAX_AMBIENT_OCCLUSION: u8 = 3;
+
+/// the ambient occlusion of every corner of a face that is not occluded at all
+const UNOCCLUDED: [u8; 4] = [MAX_AMBIENT_OCCLUSION; 4];
+
+/// whether the block at `local` darkens the face corners around it
+fn occludes(neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, local: Position) -> bool {
+    neighbourhood.get(local).is_some_and(|block| !registry.get(block).transparent)
+}
+
+/// The ambient occlusion of a face corner, from 0 for a corner tucked in between two blocks up
+/// to [`MAX_AMBIENT_OCCLUSION`] for an open one. It is worked out from the blocks touching the
+/// corner in the layer in front of the face: the two along its edges, and the one diagonally
+/// across from it, which can't darken it any further once both edges are covered.
+fn corner_occlusion(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        MAX_AMBIENT_OCCLUSION - side1 as u8 - side2 as u8 - corner as u8
+    }
+}
+
+/// The ambient occlusion of a corner of a `side` facing face, whose block in front is `front`.
+/// The corner is given by which way it lies from the middle of the face, -1 or 1 along the
+/// texture's u and v axes.
+fn occlusion_at(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    front: Position,
+    side: Side,
+    (u, v): (isize, isize),
+) -> u8 {
+    let (u_axis, v_axis) = side.texture_axes();
+    let occluded = |u, v| {
+        let mut offset = [0; 3];
+        offset[u_axis] = u;
+        offset[v_axis] = v;
+        occludes(neighbourhood, registry, front + Position::new(offset[0], offset[1], offset[2]))
+    };
+    corner_occlusion(occluded(u, 0), occluded(0, v), occluded(u, v))
+}
+
+/// the ambient occlusion of the corners of the `side` face of the block at `local`, in the order
+/// of [`Side::corners`]
+fn face_occlusion(neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, local: Position, side: Side) -> [u8; 4] {
+    let (u_axis, v_axis) = side.texture_axes();
+    side.corners().map(|corner| {
+        let direction = |axis: usize| if corner[axis] > 0.5 { 1 } else { -1 };
+        occlusion_at(neighbourhood, registry, local + side.offset(), side, (direction(u_axis), direction(v_axis)))
+    })
+}
+
+/// The two triangles of a quad whose corners, in counter-clockwise order, are occluded by
+/// `occlusion`, as indices into its corners. The quad is split along the diagonal between its
+/// two brighter opposite corners, so the occlusion is blended the same way on every face
+/// instead of depending on which way the quad happens to be cut.
+fn quad_triangles(occlusion: [u8; 4]) -> [u32; 6] {
+    if occlusion[0] + occlusion[2] >= occlusion[1] + occlusion[3] {
+        [0, 1, 2, 0, 2, 3]
+    } else {
+        [0, 1, 3, 1, 2, 3]
+    }
+}
+
+/// Vertex data of a mesh that is still being built.
+#[derive(Default)]
+pub struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    normals: Vec<[f32; 3]>,
+    layers: Vec<u32>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    /// Add a `side` facing quad covering the faces of a box of blocks `size` large, whose
+    /// minimum corner is at `offset`. The UVs count blocks, so `texture` repeats once per block
+    /// across the quad, upright on the sides of blocks. `occlusion` holds the ambient occlusion
+    /// of the quad's corners in the order of [`Side::corners`].
+    pub fn push_quad(
+        &mut self,
+        side: Side,
+        offset: Vec3,
+        size: Vec3,
+        texture: FaceTexture,
+        light: Light,
+        occlusion: [u8; 4],
+    ) {
+        let start_index = self.positions.len() as u32;
+        let corners = side.corners().map(|corner| Vec3::from(corner) * size);
+        for (corner, occlusion) in corners.into_iter().zip(occlusion) {
+            self.positions.push((corner + offset).into());
+            self.normals.push(side.normal());
+            self.layers.push(texture.layer);
+            self.colors.push(light_color(light, occlusion));
+        }
+        // the corners run along the bottom edge of the face first, then up its right edge
+        let (width, height) = (corners[0].distance(corners[1]), corners[1].distance(corners[2]));
+        self.uvs.extend_from_slice(&[[0.0, height], [width, height], [width, 0.0], [0.0, 0.0]]);
+        self.indices.extend(quad_triangles(occlusion).map(|corner| start_index + corner));
+    }
+
+    /// Add the two crossed planes of the block whose minimum corner is at `offset`, showing
+    /// `texture` lit by `light`. Each plane is made of two quads facing opposite ways, so it can
+    /// be seen from both sides.
+    #[rustfmt::skip]
+    pub fn push_cross(&mut self, offset: Vec3, texture: FaceTexture, light: Light) {
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        let planes = [
+            ([[1.0, 0.0, 1.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 1.0]], [diagonal, 0.0, -diagonal]),
+            ([[0.0, 0.0, 0.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 0.0]], [-diagonal, 0.0, diagonal]),
+            ([[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 1.0]], [diagonal, 0.0, diagonal]),
+            ([[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 1.0, 0.0]], [-diagonal, 0.0, -diagonal]),
+        ];
+        for (corners, normal) in planes {
+            let start_index = self.positions.len() as u32;
+            for corner in corners {
+                self.positions.push((Vec3::from(corner) + offset).into());
+                self.normals.push(normal);
+                self.layers.push(texture.layer);
+                self.colors.push(light_color(light, MAX_AMBIENT_OCCLUSION));
+            }
+            self.uvs.extend_from_slice(&[[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]]);
+            self.indices.extend(quad_triangles(UNOCCLUDED).map(|corner| start_index + corner));
+        }
+    }
+
+    pub fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(ATTRIBUTE_TEXTURE_LAYER, self.layers);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+/// The meshes of a chunk, one for the faces drawn with each [`RenderMode`].
+pub struct ChunkMeshes([Mesh; 3]);
+
+impl ChunkMeshes {
+    pub fn into_meshes(self) -> impl Iterator<Item = (RenderMode, Mesh)> {
+        RenderMode::ALL.into_iter().zip(self.0)
+    }
+}
+
+/// A [`MeshBuilder`] for each [`RenderMode`], so every face ends up in the mesh it is drawn with.
+#[derive(Default)]
+struct ChunkMeshBuilder([MeshBuilder; 3]);
+
+impl ChunkMeshBuilder {
+    /// the builder of the faces of `block`
+    fn layer(&mut self, registry: &BlockRegistry, block: BlockType) -> &mut MeshBuilder {
+        &mut self.0[registry.render_mode(block) as usize]
+    }
+
+    fn build(self) -> ChunkMeshes {
+        ChunkMeshes(self.0.map(MeshBuilder::build))
+    }
+}
+
+/// Build the meshes of the centre chunk of `neighbourhood`, in chunk local space. Only faces
+/// that are not hidden by a neighbouring block are emitted, including neighbours across the
+/// chunk border, and every face carries the light in front of it and, if `options` ask for it,
+/// the ambient occlusion of its corners in its vertex colours. The metadata needed by
+/// [`update_chunk_mesh`] is only returned for [`MeshingAlgorithm::Incremental`], and belongs to
+/// the opaque mesh.
+pub fn mesh_chunk(
+    neighbourhood: &ChunkNeighbourhood,
+    options: MeshingOptions,
+    registry: &BlockRegistry,
+) -> (ChunkMeshes, Option<MeshMD<u16>>) {
+    let mut builder = ChunkMeshBuilder::default();
+    mesh_crosses(neighbourhood, registry, &mut builder);
+    let ambient_occlusion = options.ambient_occlusion;
+    match options.algorithm {
+        MeshingAlgorithm::Culling => {
+            mesh_culled(neighbourhood, registry, ambient_occlusion, &RenderMode::ALL, &mut builder);
+        }
+        MeshingAlgorithm::Greedy => mesh_greedy(neighbourhood, registry, ambient_occlusion, &mut builder),
+        MeshingAlgorithm::Incremental => {
+            // `bevy_meshem` can only build a single mesh, which is kept for the opaque faces
+            let see_through = [RenderMode::Cutout, RenderMode::Translucent];
+            mesh_culled(neighbourhood, registry, ambient_occlusion, &see_through, &mut builder);
+            let (mesh, metadata) = mesh_incremental(neighbourhood, registry, ambient_occlusion);
+            let mut meshes = builder.build();
+            meshes.0[RenderMode::Opaque as usize] = mesh;
+            return (meshes, Some(metadata));
+        }
+    }
+    (builder.build(), None)
+}
+
+/// Patch `edits` into the opaque mesh of a chunk built with [`MeshingAlgorithm::Incremental`].
+/// Only edits inside the centre chunk, away from its border and from blocks that aren't opaque
+/// can be patched in, `false` is returned without touching the mesh if any other edit is given,
+/// and the meshes have to be rebuilt instead.
+/// `ambient_occlusion` has to match the [`MeshingOptions`] the mesh was built with.
+pub fn update_chunk_mesh(
+    mesh: &mut Mesh,
+    metadata: &mut MeshMD<u16>,
+    neighbourhood: &ChunkNeighbourhood,
+    edits: &[BlockEdit],
+    registry: &BlockRegistry,
+    ambient_occlusion: bool,
+) -> bool {
+    let chunk = neighbourhood.centre();
+    let chunk_coordinate = ChunkManager::chunk_coordinate(Position::from(chunk.position));
+    let opaque = |block: BlockType| registry.render_mode(block) == RenderMode::Opaque;
+    let patchable = edits.iter().all(|edit| {
+        let local = ChunkManager::local_position(edit.position);
+        ChunkManager::chunk_coordinate(edit.position) == chunk_coordinate
+            && (1..CHUNK_WIDTH as isize - 1).contains(&local.x)
+            && (1..CHUNK_DEPTH as isize - 1).contains(&local.z)
+            && opaque(edit.previous)
+            && opaque(edit.block)
+            // the faces and corners of see-through blocks around the edit may have changed too
+            && (-1..=1).all(|x| {
+                (-1..=1).all(|y| (-1..=1).all(|z| chunk.get(local + Position::new(x, y, z)).is_none_or(opaque)))
+            })
+    });
+    if !patchable {
+        return false;
+    }
+
+    for edit in edits {
+        let local = ChunkManager::local_position(edit.position);
+        // `bevy_meshem` wants the neighbours as they are now, with empty blocks as `None`
+        let mut neighbours = [None; 6];
+        for (i, neighbour) in neighbours.iter_mut().enumerate() {
+            let side = Side::from_face(Face::from(i));
+            *neighbour = chunk
+                .get(local + side.offset())
+                .filter(|block| *block != BlockType::AIR)
+                .map(BlockType::id);
+        }
+        let index = Chunk::index(local);
+        if edit.previous != BlockType::AIR {
+            metadata.log(VoxelChange::Broken, index, edit.previous.id(), neighbours);
+        }
+        if edit.block != BlockType::AIR {
+            metadata.log(VoxelChange::Added, index, edit.block.id(), neighbours);
+        }
+    }
+    update_mesh(mesh, metadata, registry);
+    light_mesh(mesh, neighbourhood, registry, ambient_occlusion);
+    true
+}
+
+/// Colour the faces of a mesh built by `bevy_meshem` with the light in front of them, and the
+/// ambient occlusion of their corners if `ambient_occlusion` is set. Its faces are quads of four
+/// vertices each, whose centre lies on the face of the block they belong to. The two triangles
+/// of every quad are cut again along the diagonal [`quad_triangles`] picks.
+fn light_mesh(mesh: &mut Mesh, neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, ambient_occlusion: bool) {
+    let (Some(VertexAttributeValues::Float32x3(positions)), Some(VertexAttributeValues::Float32x3(normals))) =
+        (mesh.attribute(Mesh::ATTRIBUTE_POSITION), mesh.attribute(Mesh::ATTRIBUTE_NORMAL))
+    else {
+        return;
+    };
+    let mut occlusion = Vec::with_capacity(positions.len());
+    let colors: Vec<[f32; 4]> = positions
+        .chunks(4)
+        .zip(normals.chunks(4))
+        .flat_map(|(corners, normals)| {
+            let centre = corners.iter().map(|corner| Vec3::from(*corner)).sum::<Vec3>() / corners.len() as f32;
+            // step from the face into the block in front of it
+            let front = Position::from((centre + Vec3::from(normals[0]) * 0.5).floor());
+            let light = neighbourhood.light(front).unwrap_or(Light::DAYLIGHT);
+            let side = Side::ALL.into_iter().find(|side| side.normal() == normals[0]);
+            let corner_occlusion: Vec<u8> = match (side, ambient_occlusion) {
+                (Some(side), true) => {
+                    let (u_axis, v_axis) = side.texture_axes();
+                    corners
+                        .iter()
+                        .map(|corner| {
+                            let direction = |axis: usize| if corner[axis] > centre[axis] { 1 } else { -1 };
+                            occlusion_at(neighbourhood, registry, front, side, (direction(u_axis), direction(v_axis)))
+                        })
+                        .collect()
+                }
+                _ => vec![MAX_AMBIENT_OCCLUSION; corners.len()],
+            };
+            occlusion.extend_from_slice(&corner_occlusion);
+            corner_occlusion.into_iter().map(move |occlusion| light_color(light, occlusion))
+        })
+        .collect();
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+
+    let Some(Indices::U32(indices)) = mesh.indices_mut() else {
+        return;
+    };
+    for triangles in indices.chunks_exact_mut(6) {
+        if let Some(corners) = quad_corners(triangles) {
+            let corner_occlusion = corners.map(|corner| occlusion[corner as usize]);
+            for (index, corner) in triangles.iter_mut().zip(quad_triangles(corner_occlusion)) {
+                *index = corners[corner as usize];
+            }
+        }
+    }
+}
+
+/// The corners of the quad made up of the two triangles in `triangles`, in the order they wind
+/// around it, starting with the corner only the first triangle has. `None` if the triangles
+/// don't share an edge.
+fn quad_corners(triangles: &[u32]) -> Option<[u32; 4]> {
+    let (first, second) = triangles.split_at(3);
+    let own = first.iter().position(|index| !second.contains(index))?;
+    let across = *second.iter().find(|index| !first.contains(index))?;
+    let (next, last) = (first[(own + 1) % 3], first[(own + 2) % 3]);
+    second.contains(&next).then_some([first[own], next, across, last])
+}
+
+/// mesh the opaque blocks of the centre chunk with `bevy_meshem`, then cull the faces hidden by
+/// the loaded chunks around it
+fn mesh_incremental(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: bool,
+) -> (Mesh, MeshMD<u16>) {
+    // blocks that aren't opaque are meshed separately, so they are left out like air
+    let grid = |chunk: &Chunk| {
+        chunk
+            .iter()
+            .map(|(_, block)| match registry.render_mode(block) {
+                RenderMode::Opaque => block.id(),
+                _ => BlockType::AIR.id(),
+            })
+            .collect::<Vec<u16>>()
+    };
+    let (mut mesh, mut metadata) = mesh_grid(
+        (CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_DEPTH),
+        &[],
+        &grid(neighbourhood.centre()),
+        registry,
+        bevy_meshem::prelude::MeshingAlgorithm::Culling,
+        None,
+    )
+    .expect("chunk grids always match the chunk dimensions");
+    for (face, x, z) in [(Face::Right, 1, 0), (Face::Left, -1, 0), (Face::Back, 0, 1), (Face::Forward, 0, -1)] {
+        if let Some(adjacent) = neighbourhood.chunk(x, z) {
+            introduce_adjacent_chunks(registry, &mut mesh, &mut metadata, face, &grid(adjacent));
+        }
+    }
+    light_mesh(&mut mesh, neighbourhood, registry, ambient_occlusion);
+    (mesh, metadata)
+}
+
+/// How high `block` at `local` reaches, 1 for whole blocks. The surface of a fluid lies lower
+/// the less of it there is, unless more of the same fluid is on top of it.
+fn block_height(neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, local: Position, block: BlockType) -> f32 {
+    if neighbourhood.get(local + Side::Top.offset()) == Some(block) {
+        return 1.0;
+    }
+    fluid_level(registry, block, neighbourhood.flow_level(local)).map_or(1.0, surface_height)
+}
+
+/// the block at `local` and how high it reaches, if its `side` face is visible
+fn visible_face(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    local: Position,
+    side: Side,
+) -> Option<(BlockType, f32)> {
+    let block = neighbourhood.centre().get(local)?;
+    if !registry.is_visible(block) || registry.shape(block) == BlockShape::Cross {
+        return None;
+    }
+    let height = block_height(neighbourhood, registry, local, block);
+    let front = local + side.offset();
+    let neighbour = neighbourhood.get(front);
+    let shown = registry.shows_face_towards(block, neighbour)
+        || match side {
+            // a lowered surface is never flush with the block on top of it
+            Side::Top => height < 1.0,
+            Side::Bottom => false,
+            // fluid shows its side above lower fluid next to it
+            _ => neighbour == Some(block) && block_height(neighbourhood, registry, front, block) < height,
+        };
+    shown.then_some((block, height))
+}
+
+/// the ambient occlusion of the corners of the `side` face of the block at `local`, or none at
+/// all if `ambient_occlusion` is off
+fn occlusion_if(
+    ambient_occlusion: bool,
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    local: Position,
+    side: Side,
+) -> [u8; 4] {
+    if ambient_occlusion {
+        face_occlusion(neighbourhood, registry, local, side)
+    } else {
+        UNOCCLUDED
+    }
+}
+
+/// mesh every block of the centre chunk shaped like a cross, lit by the light in the block
+/// itself
+fn mesh_crosses(neighbourhood: &ChunkNeighbourhood, registry: &BlockRegistry, builder: &mut ChunkMeshBuilder) {
+    let chunk = neighbourhood.centre();
+    let palette = chunk.palette();
+    if !palette.iter().any(|block| registry.shape(*block) == BlockShape::Cross) {
+        return;
+    }
+    for (local_position, block) in chunk.iter() {
+        if registry.shape(block) != BlockShape::Cross || !registry.is_visible(block) {
+            continue;
+        }
+        let light = chunk.light(local_position).unwrap_or(Light::DAYLIGHT);
+        let texture = registry.face_texture(block, Side::Forward);
+        builder.layer(registry, block).push_cross(local_position.into(), texture, light);
+    }
+}
+
+/// mesh the visible faces of the blocks drawn with one of `modes`, one quad per face
+fn mesh_culled(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: bool,
+    modes: &[RenderMode],
+    builder: &mut ChunkMeshBuilder,
+) {
+    for (local_position, block) in neighbourhood.centre().iter() {
+        if !modes.contains(&registry.render_mode(block)) {
+            continue;
+        }
+        for side in Side::ALL {
+            if let Some((block, height)) = visible_face(neighbourhood, registry, local_position, side) {
+                let light = face_light(neighbourhood, local_position, side);
+                let occlusion = occlusion_if(ambient_occlusion, neighbourhood, registry, local_position, side);
+                let texture = registry.face_texture(block, side);
+                let size = Vec3::new(1.0, height, 1.0);
+                builder.layer(registry, block).push_quad(side, local_position.into(), size, texture, light, occlusion);
+            }
+        }
+    }
+}
+
+/// A visible face in a layer swept by [`mesh_greedy`]. Only faces that are equal in everything
+/// are merged.
+#[derive(Clone, Copy, PartialEq)]
+struct GreedyFace {
+    block: BlockType,
+    /// how high the block reaches
+    height: f32,
+    light: Light,
+    occlusion: [u8; 4],
+}
+
+/// Sweep every layer of the chunk facing each side, and cover the visible faces of each layer
+/// with as few rectangles of a single block type, height and light level as possible. Rectangles are
+/// grown along the u axis first and then along the v axis for as long as the whole row matches.
+/// A face whose corners are occluded differently can't be stretched over a larger rectangle
+/// without smearing its occlusion, so it is never merged.
+fn mesh_greedy(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: bool,
+    builder: &mut ChunkMeshBuilder,
+) {
+    let dims = [CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_DEPTH];
+    for side in Side::ALL {
+        let axis = side.axis();
+        let (u_axis, v_axis) = side.texture_axes();
+        let (width, height) = (dims[u_axis], dims[v_axis]);
+        let mut mask: Vec<Option<GreedyFace>> = vec![None; width * height];
+
+        for layer in 0..dims[axis] {
+            for v in 0..height {
+                for u in 0..width {
+                    let mut local = [0; 3];
+                    local[axis] = layer as isize;
+                    local[u_axis] = u as isize;
+                    local[v_axis] = v as isize;
+                    let local = Position::new(local[0], local[1], local[2]);
+                    mask[v * width + u] = visible_face(neighbourhood, registry, local, side).map(|(block, height)| GreedyFace {
+                        block,
+                        height,
+                        light: face_light(neighbourhood, local, side),
+                        occlusion: occlusion_if(ambient_occlusion, neighbourhood, registry, local, side),
+                    });
+                }
+            }
+
+            for v in 0..height {
+                let mut u = 0;
+                while u < width {
+                    let Some(face) = mask[v * width + u] else {
+                        u += 1;
+                        continue;
+                    };
+                    let GreedyFace { block, height: block_height, light, occlusion } = face;
+                    let mergeable = occlusion.iter().all(|corner| *corner == occlusion[0]);
+                    let mut quad_width = 1;
+                    while mergeable && u + quad_width < width && mask[v * width + u + quad_width] == Some(face) {
+                        quad_width += 1;
+                    }
+                    let mut quad_height = 1;
+                    while mergeable
+                        && v + quad_height < height
+                        && (u..u + quad_width).all(|i| mask[(v + quad_height) * width + i] == Some(face))
+                    {
+                        quad_height += 1;
+                    }
+                    for row in v..v + quad_height {
+                        mask[row * width + u..row * width + u + quad_width].fill(None);
+                    }
+
+                    let mut offset = Vec3::ZERO;
+                    offset[axis] = layer as f32;
+                    offset[u_axis] = u as f32;
+                    offset[v_axis] = v as f32;
+                    let mut size = Vec3::ONE;
+                    size[u_axis] = quad_width as f32;
+                    size[v_axis] = quad_height as f32;
+                    // only the top block of a column can be lowered, so lowered faces are never
+                    // merged upwards
+                    size.y *= block_height;
+                    let texture = registry.face_texture(block, side);
+                    builder.layer(registry, block).push_quad(side, offset, size, texture, light, occlusion);
+
+                    u += quad_width;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    use crate::chunk_manager::{Chunk, ChunkManager};
+
+    /// the block type called `name` in the blocks file
+    fn block(name: &str) -> BlockType {
+        BlockRegistry::from_assets().by_name(name).unwrap()
+    }
+
+    /// a single chunk of air with `block` at each of `positions`
+    fn chunks_with(block: BlockType, positions: &[(isize, isize, isize)]) -> ChunkManager {
+        let mut chunk = Chunk::new(Vec3::ZERO, BlockType::AIR);
+        for &(x, y, z) in positions {
+            chunk.set(Position::new(x, y, z), block);
+        }
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(Position::new(0, 0, 0), chunk);
+        chunks
+    }
+
+    /// the number of quads in the mesh of the chunk at chunk coordinate `position`
+    fn quad_count(chunks: &ChunkManager, position: Position, algorithm: MeshingAlgorithm) -> usize {
+        let neighbourhood = ChunkNeighbourhood::new(chunks, position).unwrap();
+        let options = MeshingOptions { algorithm, ambient_occlusion: false };
+        let (meshes, _) = mesh_chunk(&neighbourhood, options, &BlockRegistry::from_assets());
+        meshes.into_meshes().map(|(_, mesh)| mesh.count_vertices() / 4).sum()
+    }
+
+    /// the eight corners of a 2x2x2 cube whose minimum corner is at `x`, `y`, `z`
+    fn cube(x: isize, y: isize, z: isize) -> Vec<(isize, isize, isize)> {
+        (0..8).map(|corner| (x + (corner & 1), y + (corner >> 1 & 1), z + (corner >> 2))).collect()
+    }
+
+    #[test]
+    fn only_exposed_faces_are_meshed() {
+        let origin = Position::new(0, 0, 0);
+        assert_eq!(quad_count(&chunks_with(block("dirt"), &[]), origin, MeshingAlgorithm::Culling), 0);
+        assert_eq!(quad_count(&chunks_with(block("dirt"), &[(5, 5, 5)]), origin, MeshingAlgorithm::Culling), 6);
+        // the two faces the blocks share are hidden
+        assert_eq!(quad_count(&chunks_with(block("dirt"), &[(5, 5, 5), (6, 5, 5)]), origin, MeshingAlgorithm::Culling), 10);
+    }
+
+    #[test]
+    fn faces_are_in_chunk_local_space() {
+        let chunks = chunks_with(block("dirt"), &[(5, 6, 7)]);
+        let neighbourhood = ChunkNeighbourhood::new(&chunks, Position::new(0, 0, 0)).unwrap();
+        let options = MeshingOptions { algorithm: MeshingAlgorithm::Culling, ambient_occlusion: false };
+        let (meshes, _) = mesh_chunk(&neighbourhood, options, &BlockRegistry::from_assets());
+        let (_, mesh) = meshes.into_meshes().next().unwrap();
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            panic!("chunk meshes have positions");
+        };
+        for position in positions {
+            assert!((5.0..=6.0).contains(&position[0]) && (6.0..=7.0).contains(&position[1]) && (7.0..=8.0).contains(&position[2]));
+        }
+    }
+
+    #[test]
+    fn a_solid_cube_only_shows_its_outside() {
+        let chunks = chunks_with(block("stone"), &cube(5, 5, 5));
+        assert_eq!(quad_count(&chunks, Position::new(0, 0, 0), MeshingAlgorithm::Culling), 24);
+    }
+
+    #[test]
+    fn a_cube_across_a_chunk_border_only_shows_its_outside() {
+        let mut chunks = chunks_with(block("stone"), &[]);
+        let mut neighbour = Chunk::new(Vec3::new(CHUNK_WIDTH as f32, 0.0, 0.0), BlockType::AIR);
+        for (x, y, z) in cube(15, 5, 5) {
+            let local = Position::new(x.rem_euclid(CHUNK_WIDTH as isize), y, z);
+            match x {
+                15 => chunks.chunks.get_mut(&Position::new(0, 0, 0)).unwrap().set(local, block("stone")),
+                _ => neighbour.set(local, block("stone")),
+            }
+        }
+        // nothing is known about an unloaded chunk, so the faces towards it are shown
+        assert_eq!(quad_count(&chunks, Position::new(0, 0, 0), MeshingAlgorithm::Culling), 16);
+        chunks.chunks.insert(Position::new(1, 0, 0), neighbour);
+        // each chunk holds half of the cube, and hides the faces against the other half
+        assert_eq!(quad_count(&chunks, Position::new(0, 0, 0), MeshingAlgorithm::Culling), 12);
+        assert_eq!(quad_count(&chunks, Position::new(1, 0, 0), MeshingAlgorithm::Culling), 12);
+    }
+
+    #[test]
+    fn faces_behind_transparent_blocks_are_shown() {
+        let mut chunks = chunks_with(block("stone"), &[(5, 5, 5)]);
+        let chunk = chunks.chunks.get_mut(&Position::new(0, 0, 0)).unwrap();
+        chunk.set(Position::new(6, 5, 5), block("leaves"));
+        // all of the stone, but the leaves hide their face against the stone
+        assert_eq!(quad_count(&chunks, Position::new(0, 0, 0), MeshingAlgorithm::Culling), 6 + 5);
+
+        // the faces between two blocks of water are hidden, like between two opaque blocks
+        let water = chunks_with(block("water"), &[(5, 5, 5), (5, 5, 6)]);
+        assert_eq!(quad_count(&water, Position::new(0, 0, 0), MeshingAlgorithm::Culling), 10);
+    }
+
+    #[test]
+    fn greedy_meshing_merges_flat_terrain_and_repeats_its_texture() {
+        let layer: Vec<_> = (0..CHUNK_WIDTH as isize)
+            .flat_map(|x| (0..CHUNK_DEPTH as isize).map(move |z| (x, 5, z)))
+            .collect();
+        let chunks = chunks_with(block("stone"), &layer);
+        let origin = Position::new(0, 0, 0);
+        // a face for every block on the top and bottom, and along the open chunk borders
+        let faces = 2 * CHUNK_WIDTH * CHUNK_DEPTH + 2 * (CHUNK_WIDTH + CHUNK_DEPTH);
+        assert_eq!(quad_count(&chunks, origin, MeshingAlgorithm::Culling), faces);
+        // one quad for each side of the layer
+        assert_eq!(quad_count(&chunks, origin, MeshingAlgorithm::Greedy), 6);
+
+        let neighbourhood = ChunkNeighbourhood::new(&chunks, origin).unwrap();
+        let options = MeshingOptions { algorithm: MeshingAlgorithm::Greedy, ambient_occlusion: false };
+        let (meshes, _) = mesh_chunk(&neighbourhood, options, &BlockRegistry::from_assets());
+        let (_, mesh) = meshes.into_meshes().next().unwrap();
+        let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+            panic!("chunk meshes have positions");
+        };
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else {
+            panic!("chunk meshes have UVs");
+        };
+        // the UVs of each quad span as many textures as the quad spans blocks
+        let extent = |values: &[f32]| values.iter().fold(f32::MIN, |a, b| a.max(*b)) - values.iter().fold(f32::MAX, |a, b| a.min(*b));
+        for (positions, uvs) in positions.chunks(4).zip(uvs.chunks(4)) {
+            let mut blocks: Vec<f32> = (0..3)
+                .map(|axis| extent(&positions.iter().map(|position| position[axis]).collect::<Vec<_>>()))
+                .filter(|size| *size != 0.0)
+                .collect();
+            let mut textures: Vec<f32> = (0..2).map(|axis| extent(&uvs.iter().map(|uv| uv[axis]).collect::<Vec<_>>())).collect();
+            blocks.sort_by(f32::total_cmp);
+            textures.sort_by(f32::total_cmp);
+            assert_eq!(blocks, textures);
+        }
+    }
+
+    /// the ambient occlusion of the top face of the block at 5, 5, 5, with stone there and at
+    /// each of `around`
+    fn top_occlusion(around: &[(isize, isize, isize)]) -> [u8; 4] {
+        let registry = BlockRegistry::from_assets();
+        let chunks = chunks_with(block("stone"), &[&[(5, 5, 5)], around].concat());
+        let neighbourhood = ChunkNeighbourhood::new(&chunks, Position::new(0, 0, 0)).unwrap();
+        face_occlusion(&neighbourhood, &registry, Position::new(5, 5, 5), Side::Top)
+    }
+
+    #[test]
+    fn open_corners_are_not_occluded() {
+        assert_eq!(top_occlusion(&[]), UNOCCLUDED);
+        // blocks beside the face, but not above it, don't darken it
+        assert_eq!(top_occlusion(&[(6, 5, 5), (5, 5, 6)]), UNOCCLUDED);
+    }
+
+    #[test]
+    fn blocks_around_a_corner_occlude_it() {
+        // the corners of the top face run (0, 1), (1, 1), (1, 0), (0, 0) in x and z
+        // a block along the +x edge darkens both corners on that edge
+        assert_eq!(top_occlusion(&[(6, 6, 5)]), [3, 2, 2, 3]);
+        // a block diagonally across darkens only the corner it touches
+        assert_eq!(top_occlusion(&[(6, 6, 6)]), [3, 2, 3, 3]);
+        assert_eq!(top_occlusion(&[(6, 6, 5), (6, 6, 6)]), [3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn a_corner_between_two_blocks_is_fully_occluded() {
+        assert_eq!(top_occlusion(&[(6, 6, 5), (5, 6, 6)]), [2, 0, 2, 3]);
+        // whether or not the corner block is there too
+        assert_eq!(top_occlusion(&[(6, 6, 5), (5, 6, 6), (6, 6, 6)]), [2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn quads_are_split_between_their_brighter_corners() {
+        assert_eq!(quad_triangles(UNOCCLUDED), [0, 1, 2, 0, 2, 3]);
+        assert_eq!(quad_triangles([3, 0, 3, 3]), [0, 1, 2, 0, 2, 3]);
+        assert_eq!(quad_triangles([0, 3, 3, 3]), [0, 1, 3, 1, 2, 3]);
+        assert_eq!(quad_triangles([2, 0, 2, 3]), [0, 1, 2, 0, 2, 3]);
+        assert_eq!(quad_triangles([3, 2, 2, 3]), [0, 1, 2, 0, 2, 3]);
+
+        // the corners of either split wind the same way around the quad
+        assert_eq!(quad_corners(&[4, 5, 6, 4, 6, 7]), Some([5, 6, 7, 4]));
+        assert_eq!(quad_corners(&[4, 5, 7, 5, 6, 7]), Some([4, 5, 6, 7]));
+        assert_eq!(quad_corners(&[0, 1, 2, 3, 4, 5]), None);
+    }
+
+    #[test]
+    fn ambient_occlusion_is_optional_for_every_algorithm() {
+        let registry = BlockRegistry::from_assets();
+        // a step, whose upper block darkens the top of the lower one
+        let chunks = chunks_with(block("stone"), &[(5, 5, 5), (6, 5, 5), (6, 6, 5)]);
+        let neighbourhood = ChunkNeighbourhood::new(&chunks, Position::new(0, 0, 0)).unwrap();
+        for algorithm in [MeshingAlgorithm::Culling, MeshingAlgorithm::Greedy, MeshingAlgorithm::Incremental] {
+            let occlusion = |ambient_occlusion| {
+                let options = MeshingOptions { algorithm, ambient_occlusion };
+                let (meshes, _) = mesh_chunk(&neighbourhood, options, &registry);
+                let (_, mesh) = meshes.into_meshes().next().unwrap();
+                let Some(VertexAttributeValues::Float32x4(colors)) = mesh.attribute(Mesh::ATTRIBUTE_COLOR) else {
+                    panic!("chunk meshes are coloured");
+                };
+                colors.iter().map(|color| color[2]).fold(1.0, f32::min)
+            };
+            assert_eq!(occlusion(false), 1.0, "{algorithm:?}");
+            assert!(occlusion(true) < 1.0, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn faces_are_split_by_render_mode() {
+        let registry = BlockRegistry::from_assets();
+        let mut chunks = chunks_with(block("stone"), &[(5, 5, 5)]);
+        let chunk = chunks.chunks.get_mut(&Position::new(0, 0, 0)).unwrap();
+        chunk.set(Position::new(7, 5, 5), registry.by_name("leaves").unwrap());
+        // two blocks of water touching along z = 6
+        chunk.set(Position::new(9, 5, 5), registry.by_name("water").unwrap());
+        chunk.set(Position::new(9, 5, 6), registry.by_name("water").unwrap());
+        let neighbourhood = ChunkNeighbourhood::new(&chunks, Position::new(0, 0, 0)).unwrap();
+
+        for algorithm in [MeshingAlgorithm::Culling, MeshingAlgorithm::Greedy, MeshingAlgorithm::Incremental] {
+            let options = MeshingOptions { algorithm, ambient_occlusion: true };
+            let (meshes, _) = mesh_chunk(&neighbourhood, options, &registry);
+            for (mode, mesh) in meshes.into_meshes() {
+                let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+                    panic!("chunk meshes have positions");
+                };
+                let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else {
+                    panic!("chunk meshes have normals");
+                };
+                let xs: Vec<f32> = positions.iter().map(|position| position[0]).collect();
+                let within = |min: f32, max: f32| xs.iter().all(|x| (min..=max).contains(x));
+                match mode {
+                    // all six faces of the stone and of the leaves
+                    RenderMode::Opaque => assert!(positions.len() == 24 && within(5.0, 6.0), "{algorithm:?}"),
+                    RenderMode::Cutout => assert!(positions.len() == 24 && within(7.0, 8.0), "{algorithm:?}"),
+                    RenderMode::Translucent => {
+                        assert!(!positions.is_empty() && within(9.0, 10.0), "{algorithm:?}");
+                        // but not the faces between the two blocks of water
+                        let between = positions.iter().zip(normals).any(|(position, normal)| position[2] == 6.0 && normal[2] != 0.0);
+                        assert!(!between, "{algorithm:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fluid_surfaces_are_lowered_by_their_level() {
+        let registry = BlockRegistry::from_assets();
+        let water = registry.by_name("water").unwrap();
+        let mut chunks = chunks_with(water, &[]);
+        let chunk = chunks.chunks.get_mut(&Position::new(0, 0, 0)).unwrap();
+        // a source with water flowing out of it along x, and more water on top of the source
+        chunk.set(Position::new(5, 5, 5), water);
+        chunk.set(Position::new(5, 6, 5), water);
+        chunk.set(Position::new(6, 5, 5), water);
+        chunk.set_flow_level(Position::new(6, 5, 5), Some(4));
+        let neighbourhood = ChunkNeighbourhood::new(&chunks, Position::new(0, 0, 0)).unwrap();
+
+        for algorithm in [MeshingAlgorithm::Culling, MeshingAlgorithm::Greedy, MeshingAlgorithm::Incremental] {
+            let options = MeshingOptions { algorithm, ambient_occlusion: true };
+            let (meshes, _) = mesh_chunk(&neighbourhood, options, &registry);
+            let (_, mesh) = meshes.into_meshes().last().unwrap();
+            let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+                panic!("chunk meshes have positions");
+            };
+            let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute(Mesh::ATTRIBUTE_NORMAL) else {
+                panic!("chunk meshes have normals");
+            };
+            // the height of the top faces with corners at `x`
+            let top = |x: f32| {
+                positions
+                    .iter()
+                    .zip(normals)
+                    .filter(|(position, normal)| normal[1] == 1.0 && position[0] == x)
+                    .map(|(position, _)| position[1])
+                    .fold(0.0, f32::max)
+            };
+            // the covered source reaches the top of its block and the water on it is a source
+            assert_eq!(top(5.0), 6.0 + surface_height(8), "{algorithm:?}");
+            assert_eq!(top(7.0), 5.0 + surface_height(4), "{algorithm:?}");
+            // the side of the source shows above the lower water next to it
+            let side = positions.iter().zip(normals).any(|(position, normal)| normal[0] == 1.0 && position[0] == 6.0 && position[1] == 6.0);
+            assert!(side, "{algorithm:?}");
+        }
+    }
+}
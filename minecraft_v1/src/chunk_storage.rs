@@ -0,0 +1,282 @@
+use std::io::{self, Read, Write};
+
+use crate::block_types::BlockType;
+
+/// Dense, palette compressed storage for the blocks of a chunk.
+///
+/// Every block is stored as an index into `palette`, packed into `u64` words using as few bits
+/// as the palette needs. A chunk made of a single block type needs no index data at all, and a
+/// chunk with up to 16 different block types only needs 4 bits per block.
+#[derive(Clone, Debug)]
+pub struct PalettedStorage {
+    /// every distinct block type that has been written into the storage
+    palette: Vec<BlockType>,
+    /// how many bits each index takes up. 0 when the palette only has one entry
+    bits: u32,
+    /// the packed palette indices. Indices never straddle two words
+    data: Vec<u64>,
+    /// how many blocks the storage holds
+    len: usize,
+}
+
+impl PalettedStorage {
+    /// create a storage of `len` blocks that are all `fill`
+    pub fn new(len: usize, fill: BlockType) -> Self {
+        Self {
+            palette: vec![fill],
+            bits: 0,
+            data: Vec::new(),
+            len,
+        }
+    }
+
+    /// the block at `index`
+    ///
+    /// # Panics
+    /// if `index` is out of bounds
+    pub fn get(&self, index: usize) -> BlockType {
+        self.palette[self.palette_index(index)]
+    }
+
+    /// the index into the palette of the block at `index`
+    fn palette_index(&self, index: usize) -> usize {
+        assert!(index < self.len, "index {index} out of bounds for storage of {}", self.len);
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = Self::per_word(self.bits);
+        let word = self.data[index / per_word];
+        let shift = (index % per_word) as u32 * self.bits;
+        ((word >> shift) & Self::mask(self.bits)) as usize
+    }
+
+    /// replace the block at `index`, growing the palette if `block` is new to this storage
+    ///
+    /// # Panics
+    /// if `index` is out of bounds
+    pub fn set(&mut self, index: usize, block: BlockType) {
+        assert!(index < self.len, "index {index} out of bounds for storage of {}", self.len);
+        let palette_index = match self.palette.iter().position(|b| *b == block) {
+            Some(i) => i,
+            None => {
+                self.palette.push(block);
+                let needed = Self::bits_for(self.palette.len());
+                if needed > self.bits {
+                    self.repack(needed);
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits == 0 {
+            return;
+        }
+        let per_word = Self::per_word(self.bits);
+        let word = &mut self.data[index / per_word];
+        let shift = (index % per_word) as u32 * self.bits;
+        *word &= !(Self::mask(self.bits) << shift);
+        *word |= (palette_index as u64) << shift;
+    }
+
+    /// every block type that has been written into the storage, some of which may have been
+    /// overwritten since
+    pub fn palette(&self) -> &[BlockType] {
+        &self.palette
+    }
+
+    /// every block in index order
+    pub fn iter(&self) -> impl Iterator<Item = BlockType> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// Write the palette and the packed indices to `out`. Palette entries that are no longer used
+    /// by any block are left out.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len {
+            used[self.palette_index(i)] = true;
+        }
+        if used.contains(&false) {
+            let mut compacted = self.clone();
+            compacted.compact(&used);
+            return compacted.write_to(out);
+        }
+
+        out.write_all(&(self.palette.len() as u16).to_le_bytes())?;
+        for block in &self.palette {
+            out.write_all(&block.id().to_le_bytes())?;
+        }
+        for word in &self.data {
+            out.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// read a storage of `len` blocks written by [`PalettedStorage::write_to`]
+    pub fn read_from(input: &mut impl Read, len: usize) -> io::Result<Self> {
+        let palette_len = read_u16(input)? as usize;
+        if palette_len == 0 {
+            return Err(invalid_data("empty block palette"));
+        }
+        let mut palette = Vec::with_capacity(palette_len);
+        for _ in 0..palette_len {
+            palette.push(BlockType::from_id(read_u16(input)?));
+        }
+
+        let bits = Self::bits_for(palette_len);
+        let words = if bits == 0 { 0 } else { len.div_ceil(Self::per_word(bits)) };
+        let mut data = Vec::with_capacity(words);
+        for _ in 0..words {
+            let mut word = [0; 8];
+            input.read_exact(&mut word)?;
+            data.push(u64::from_le_bytes(word));
+        }
+
+        let storage = Self { palette, bits, data, len };
+        if (0..len).any(|i| storage.palette_index(i) >= palette_len) {
+            return Err(invalid_data("block index out of palette bounds"));
+        }
+        Ok(storage)
+    }
+
+    /// drop the palette entries that aren't `used`, shrinking the indices if possible
+    fn compact(&mut self, used: &[bool]) {
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (i, block) in self.palette.iter().enumerate() {
+            if used[i] {
+                remap[i] = palette.len();
+                palette.push(*block);
+            }
+        }
+        let indices: Vec<usize> = (0..self.len).map(|i| remap[self.palette_index(i)]).collect();
+
+        self.palette = palette;
+        self.pack(indices, Self::bits_for(self.palette.len()));
+    }
+
+    /// rewrite the packed indices using `bits` bits per block
+    fn repack(&mut self, bits: u32) {
+        let indices: Vec<usize> = (0..self.len).map(|i| self.palette_index(i)).collect();
+        self.pack(indices, bits);
+    }
+
+    /// replace the packed indices with `indices`, using `bits` bits per block
+    fn pack(&mut self, indices: Vec<usize>, bits: u32) {
+        self.bits = bits;
+        self.data = Vec::new();
+        if bits == 0 {
+            return;
+        }
+        let per_word = Self::per_word(bits);
+        self.data = vec![0; self.len.div_ceil(per_word)];
+        for (i, palette_index) in indices.into_iter().enumerate() {
+            let shift = (i % per_word) as u32 * bits;
+            self.data[i / per_word] |= (palette_index as u64) << shift;
+        }
+    }
+
+    /// the number of bits needed to address a palette of `palette_len` entries
+    fn bits_for(palette_len: usize) -> u32 {
+        if palette_len <= 1 {
+            0
+        } else {
+            usize::BITS - (palette_len - 1).leading_zeros()
+        }
+    }
+
+    fn per_word(bits: u32) -> usize {
+        (u64::BITS / bits) as usize
+    }
+
+    fn mask(bits: u32) -> u64 {
+        (1 << bits) - 1
+    }
+}
+
+/// Two storages are equal when they hold the same blocks, however their palettes are ordered.
+impl PartialEq for PalettedStorage {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    input.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// write `storage` out and read it back in
+    fn round_trip(storage: &PalettedStorage) -> PalettedStorage {
+        let mut bytes = Vec::new();
+        storage.write_to(&mut bytes).unwrap();
+        PalettedStorage::read_from(&mut bytes.as_slice(), storage.len).unwrap()
+    }
+
+    #[test]
+    fn a_single_block_type_needs_no_index_data() {
+        let storage = PalettedStorage::new(4096, BlockType::from_id(3));
+        assert_eq!(storage.bits, 0);
+        assert!(storage.data.is_empty());
+        assert!(storage.iter().all(|block| block == BlockType::from_id(3)));
+    }
+
+    #[test]
+    fn indices_grow_with_the_palette() {
+        let block = |i: usize| BlockType::from_id((i * 7 % 5) as u16);
+        let mut storage = PalettedStorage::new(4096, BlockType::AIR);
+        // five block types scattered over the storage, so repacking has to keep what was there
+        for i in 0..4096 {
+            storage.set(i, block(i));
+        }
+        assert_eq!(storage.bits, 3);
+        assert_eq!(storage.palette.len(), 5);
+        assert!(storage.iter().enumerate().all(|(i, stored)| stored == block(i)));
+
+        storage.set(4095, BlockType::from_id(7));
+        assert_eq!(storage.get(4095), BlockType::from_id(7));
+        assert_eq!(storage.get(4094), block(4094));
+    }
+
+    #[test]
+    fn a_single_block_type_round_trips_without_index_data() {
+        let storage = PalettedStorage::new(4096, BlockType::from_id(3));
+        let mut bytes = Vec::new();
+        storage.write_to(&mut bytes).unwrap();
+        // the palette length and its only entry
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(round_trip(&storage), storage);
+    }
+
+    #[test]
+    fn paletted_blocks_round_trip() {
+        let mut storage = PalettedStorage::new(4096, BlockType::AIR);
+        // more block types than fit in 4 bits, scattered over the storage
+        for i in 0..4096 {
+            storage.set(i, BlockType::from_id((i * 7 % 20) as u16));
+        }
+        assert_eq!(storage.bits, 5);
+        let read = round_trip(&storage);
+        assert_eq!(read, storage);
+        assert_eq!(read.bits, 5);
+    }
+
+    #[test]
+    fn overwritten_block_types_are_not_written() {
+        let mut storage = PalettedStorage::new(4096, BlockType::AIR);
+        storage.set(10, BlockType::from_id(1));
+        storage.set(20, BlockType::from_id(2));
+        storage.set(10, BlockType::AIR);
+        let read = round_trip(&storage);
+        assert_eq!(read, storage);
+        assert_eq!(read.palette, [BlockType::AIR, BlockType::from_id(2)]);
+    }
+}
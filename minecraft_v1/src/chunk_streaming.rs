@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+
+use crate::block_types::BlockRegistry;
+use crate::chunk_manager::{ChunkManager, Position};
+use crate::decoration::{place_writes, DeferredWrites};
+use crate::lighting::light_chunk;
+use crate::terrain_generator::{GeneratedChunk, TerrainGenerator};
+use crate::world_storage::WorldStorage;
+
+pub struct ChunkStreamingPlugin;
+
+impl Plugin for ChunkStreamingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<StreamingSettings>()
+            .init_resource::<GenerationTasks>()
+            .add_event::<ChunkLoaded>()
+            .add_event::<ChunkUnloaded>()
+            .add_systems(Update, (unload_distant_chunks, load_nearby_chunks, receive_generated_chunks).chain());
+    }
+}
+
+/// Controls which chunks are kept loaded around the camera.
+#[derive(Resource)]
+pub struct StreamingSettings {
+    /// chunks up to this many chunks away from the camera's chunk are loaded
+    pub render_distance: isize,
+    /// the most chunks that start generating in a single frame, so walking into new terrain
+    /// doesn't flood the task pool
+    pub chunks_per_frame: usize,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            render_distance: 8,
+            chunks_per_frame: 4,
+        }
+    }
+}
+
+impl StreamingSettings {
+    /// Whether the chunk at chunk coordinate `chunk` stays loaded around `camera`. Chunks are
+    /// kept for one chunk past the render distance, so walking back and forth over a chunk border
+    /// doesn't reload them.
+    pub fn keeps_loaded(&self, camera: &GlobalTransform, chunk: Position) -> bool {
+        let keep_distance = self.render_distance + 1;
+        chunk_distance_squared(chunk, camera_chunk(camera)) <= keep_distance.pow(2)
+    }
+}
+
+/// Sent after the chunk at the held chunk coordinate was inserted into the [`ChunkManager`].
+#[derive(Event)]
+pub struct ChunkLoaded(pub Position);
+
+/// Sent after the chunk at the held chunk coordinate was removed from the [`ChunkManager`].
+#[derive(Event)]
+pub struct ChunkUnloaded(pub Position);
+
+/// The chunks being generated on the [`AsyncComputeTaskPool`]. Dropping a task cancels it.
+#[derive(Resource, Default)]
+pub struct GenerationTasks(HashMap<Position, Task<GeneratedChunk>>);
+
+/// the chunk coordinate of the chunk the camera is in
+fn camera_chunk(camera: &GlobalTransform) -> Position {
+    ChunkManager::chunk_coordinate(Position::from(camera.translation().floor()))
+}
+
+/// the squared distance in chunks between two chunk coordinates
+fn chunk_distance_squared(a: Position, b: Position) -> isize {
+    (a.x - b.x).pow(2) + (a.z - b.z).pow(2)
+}
+
+/// Everything the blocks of a chunk that is loaded come from.
+#[derive(SystemParam)]
+struct ChunkSources<'w> {
+    generator: Res<'w, TerrainGenerator>,
+    storage: Res<'w, WorldStorage>,
+    registry: Res<'w, BlockRegistry>,
+    deferred: Res<'w, DeferredWrites>,
+}
+
+/// Start loading the missing chunks within the render distance, nearest first. Chunks that were
+/// saved are read back from the [`WorldStorage`], every other chunk is generated. Either way
+/// the [`DeferredWrites`] into the chunk are placed and it is lit on its own before it is
+/// loaded. Saved chunks were decorated when they were first generated, so nothing of them
+/// reaches into the chunks around them.
+fn load_nearby_chunks(
+    settings: Res<StreamingSettings>,
+    sources: ChunkSources,
+    chunks: Res<ChunkManager>,
+    mut tasks: ResMut<GenerationTasks>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let centre = camera_chunk(camera);
+    let distance = settings.render_distance;
+
+    let mut missing = Vec::new();
+    for x in -distance..=distance {
+        for z in -distance..=distance {
+            let position = centre + Position::new(x, 0, z);
+            if chunk_distance_squared(position, centre) <= distance.pow(2)
+                && !chunks.chunks.contains_key(&position)
+                && !tasks.0.contains_key(&position)
+            {
+                missing.push(position);
+            }
+        }
+    }
+    missing.sort_by_key(|position| chunk_distance_squared(*position, centre));
+
+    let pool = AsyncComputeTaskPool::get();
+    for position in missing.into_iter().take(settings.chunks_per_frame) {
+        let generator = sources.generator.clone();
+        let storage = sources.storage.clone();
+        let registry = sources.registry.clone();
+        let writes = sources.deferred.writes_into(position);
+        let task = pool.spawn(async move {
+            let mut generated = match storage.load_chunk(position, &registry) {
+                Ok(Some(chunk)) => GeneratedChunk { chunk, overflow: Vec::new() },
+                Ok(None) => generator.generate_chunk(position, &registry),
+                Err(error) => {
+                    error!("failed to load chunk {position:?}, generating it instead: {error}");
+                    generator.generate_chunk(position, &registry)
+                }
+            };
+            place_writes(&mut generated.chunk, &writes, &registry);
+            light_chunk(&mut generated.chunk, &registry);
+            generated
+        });
+        tasks.0.insert(position, task);
+    }
+}
+
+/// Move the chunks that finished generating into the [`ChunkManager`], and the blocks their
+/// decorations place into the chunks around them into the [`DeferredWrites`]. Writes deferred
+/// into a chunk while it was generated are placed into it on the way.
+pub fn receive_generated_chunks(
+    mut tasks: ResMut<GenerationTasks>,
+    mut chunks: ResMut<ChunkManager>,
+    mut deferred: ResMut<DeferredWrites>,
+    registry: Res<BlockRegistry>,
+    mut loaded: EventWriter<ChunkLoaded>,
+) {
+    let finished: Vec<Position> = tasks
+        .0
+        .iter()
+        .filter(|(_, task)| task.is_finished())
+        .map(|(position, _)| *position)
+        .collect();
+    for position in finished {
+        let task = tasks.0.remove(&position).expect("finished tasks are still queued");
+        let mut generated = block_on(task);
+        deferred.place_into(position, &mut generated.chunk, &registry);
+        chunks.chunks.insert(position, generated.chunk);
+        deferred.defer(position, generated.overflow);
+        loaded.send(ChunkLoaded(position));
+    }
+}
+
+/// Drop the chunks that are out of the render distance, and cancel generating the ones that left
+/// it before they were done, see [`StreamingSettings::keeps_loaded`]. Edited chunks are saved
+/// before they are dropped, and stay loaded if saving them fails.
+pub fn unload_distant_chunks(
+    settings: Res<StreamingSettings>,
+    storage: Res<WorldStorage>,
+    mut chunks: ResMut<ChunkManager>,
+    mut tasks: ResMut<GenerationTasks>,
+    mut deferred: ResMut<DeferredWrites>,
+    mut unloaded: EventWriter<ChunkUnloaded>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    tasks.0.retain(|position, _| settings.keeps_loaded(camera, *position));
+
+    let mut distant: Vec<Position> = chunks
+        .chunks
+        .keys()
+        .copied()
+        .filter(|position| !settings.keeps_loaded(camera, *position))
+        .collect();
+    let edited = distant
+        .iter()
+        .map(|position| (*position, &chunks.chunks[position]))
+        .filter(|(_, chunk)| chunk.is_dirty());
+    if let Err(error) = storage.save_chunks(edited) {
+        error!("failed to save unloaded chunks: {error}");
+        distant.retain(|position| !chunks.chunks[position].is_dirty());
+    }
+    for position in distant {
+        if chunks.chunks[&position].is_dirty() {
+            deferred.saved(position);
+        }
+        chunks.chunks.remove(&position);
+        unloaded.send(ChunkUnloaded(position));
+    }
+}
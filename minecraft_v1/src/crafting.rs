@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy::{asset::io::file::FileAssetReader, prelude::*};
+use serde::Deserialize;
+
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::inventory::ItemStack;
+
+/// the file the recipes are defined in, relative to the assets directory
+const RECIPES_FILE: &str = "recipes.ron";
+/// the width and height of the largest crafting grid, the crafting table's
+pub const MAX_GRID_SIZE: usize = 3;
+
+/// Loads the [`RecipeBook`].
+pub struct CraftingPlugin;
+
+impl Plugin for CraftingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RecipeBook>();
+    }
+}
+
+/// A recipe as written in the recipes file, naming its items by block name.
+#[derive(Deserialize, Debug)]
+enum RecipeDefinition {
+    Shaped {
+        /// the rows of the recipe, where a space is an empty cell and every other character is
+        /// the item it maps to in `key`
+        pattern: Vec<String>,
+        key: HashMap<char, String>,
+        output: OutputDefinition,
+    },
+    Shapeless {
+        ingredients: Vec<String>,
+        output: OutputDefinition,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct OutputDefinition {
+    item: String,
+    #[serde(default = "default_output_count")]
+    count: u16,
+}
+
+fn default_output_count() -> u16 {
+    1
+}
+
+/// What has to be in a crafting grid for a [`Recipe`] to match.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Ingredients {
+    /// the items laid out in a `width` by `height` rectangle, row by row, which can sit anywhere
+    /// in the grid and match mirrored left to right. The rest of the grid has to be empty
+    Shaped {
+        width: usize,
+        height: usize,
+        cells: Vec<Option<BlockType>>,
+    },
+    /// exactly these items, one per cell, in any cells of the grid
+    Shapeless(Vec<BlockType>),
+}
+
+/// A way to turn the items in a crafting grid into a new stack.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Recipe {
+    pub ingredients: Ingredients,
+    pub output: ItemStack,
+}
+
+impl Recipe {
+    /// Whether the items in a `width` by `height` crafting grid, given row by row, are the
+    /// ingredients of this recipe.
+    pub fn matches(&self, width: usize, height: usize, cells: &[Option<BlockType>]) -> bool {
+        assert_eq!(cells.len(), width * height, "a {width}x{height} grid has {} cells", width * height);
+        match &self.ingredients {
+            Ingredients::Shaped {
+                width: pattern_width,
+                height: pattern_height,
+                cells: pattern,
+            } => {
+                let Some((trimmed_width, trimmed)) = trim(width, height, cells) else {
+                    return false;
+                };
+                if trimmed_width != *pattern_width || trimmed.len() != pattern_width * pattern_height {
+                    return false;
+                }
+                trimmed == *pattern || trimmed == mirror(*pattern_width, pattern)
+            }
+            Ingredients::Shapeless(ingredients) => {
+                let mut items: Vec<u16> = cells.iter().flatten().map(|block| block.id()).collect();
+                let mut ingredients: Vec<u16> = ingredients.iter().map(|block| block.id()).collect();
+                items.sort_unstable();
+                ingredients.sort_unstable();
+                items == ingredients
+            }
+        }
+    }
+}
+
+/// Cut the empty rows and columns around the items in a `width` by `height` grid, returning the
+/// width and cells of what is left, or `None` if the grid is empty.
+fn trim(width: usize, height: usize, cells: &[Option<BlockType>]) -> Option<(usize, Vec<Option<BlockType>>)> {
+    let filled = |x: usize, y: usize| cells[y * width + x].is_some();
+    let columns: Vec<usize> = (0..width).filter(|&x| (0..height).any(|y| filled(x, y))).collect();
+    let rows: Vec<usize> = (0..height).filter(|&y| (0..width).any(|x| filled(x, y))).collect();
+    let (&left, &right) = (columns.first()?, columns.last()?);
+    let (&top, &bottom) = (rows.first()?, rows.last()?);
+    let trimmed = (top..=bottom)
+        .flat_map(|y| (left..=right).map(move |x| cells[y * width + x]))
+        .collect();
+    Some((right - left + 1, trimmed))
+}
+
+/// flip the rows of a grid `width` cells wide left to right
+fn mirror(width: usize, cells: &[Option<BlockType>]) -> Vec<Option<BlockType>> {
+    cells.chunks(width).flat_map(|row| row.iter().rev().copied()).collect()
+}
+
+/// Every crafting recipe, loaded from [`RECIPES_FILE`] in the assets directory. If the file can't
+/// be loaded the error is logged and nothing can be crafted.
+#[derive(Resource, Debug)]
+pub struct RecipeBook {
+    recipes: Vec<Recipe>,
+}
+
+impl FromWorld for RecipeBook {
+    fn from_world(world: &mut World) -> Self {
+        let registry = world
+            .get_resource::<BlockRegistry>()
+            .expect("the BlockSpawnerPlugin has to be added before the recipes are loaded");
+        let path = FileAssetReader::get_base_path().join("assets").join(RECIPES_FILE);
+        match Self::load(&path, |name| registry.by_name(name)) {
+            Ok(book) => book,
+            Err(error) => {
+                error!("failed to load the recipes from {}: {error}", path.display());
+                Self { recipes: Vec::new() }
+            }
+        }
+    }
+}
+
+impl RecipeBook {
+    /// read the recipes from the RON file at `path`
+    pub fn load(path: &Path, lookup: impl Fn(&str) -> Option<BlockType>) -> io::Result<Self> {
+        Self::from_ron(&fs::read_to_string(path)?, lookup)
+    }
+
+    /// Build the recipe book from a RON list of recipes, looking up the block called by each item
+    /// name with `lookup`. Every item has to exist, shaped patterns have to be rectangular, no
+    /// larger than the crafting table and only use characters in their key, and outputs can't
+    /// be empty.
+    pub fn from_ron(text: &str, lookup: impl Fn(&str) -> Option<BlockType>) -> io::Result<Self> {
+        let definitions: Vec<RecipeDefinition> = ron::from_str(text).map_err(|error| invalid_data(error.to_string()))?;
+        let item = |name: &str| lookup(name).ok_or_else(|| invalid_data(format!("there is no item called {name}")));
+        let output = |output: &OutputDefinition| {
+            if output.count == 0 {
+                return Err(invalid_data(format!("a recipe for {} makes nothing", output.item)));
+            }
+            Ok(ItemStack {
+                block: item(&output.item)?,
+                count: output.count,
+            })
+        };
+
+        let mut recipes = Vec::with_capacity(definitions.len());
+        for definition in &definitions {
+            let recipe = match definition {
+                RecipeDefinition::Shaped { pattern, key, output: out } => {
+                    let output = output(out)?;
+                    let width = pattern.first().map_or(0, |row| row.chars().count());
+                    let height = pattern.len();
+                    if width == 0 || pattern.iter().any(|row| row.chars().count() != width) {
+                        return Err(invalid_data(format!("the pattern for {} isn't a rectangle", out.item)));
+                    }
+                    if width > MAX_GRID_SIZE || height > MAX_GRID_SIZE {
+                        return Err(invalid_data(format!("the pattern for {} doesn't fit a crafting table", out.item)));
+                    }
+                    let mut cells = Vec::with_capacity(width * height);
+                    for symbol in pattern.iter().flat_map(|row| row.chars()) {
+                        cells.push(match symbol {
+                            ' ' => None,
+                            _ => {
+                                let name = key.get(&symbol).ok_or_else(|| {
+                                    invalid_data(format!("the pattern for {} uses '{symbol}', which isn't in its key", out.item))
+                                })?;
+                                Some(item(name)?)
+                            }
+                        });
+                    }
+                    // patterns are matched against the grid without the empty rows and columns
+                    // around the items, so they have to be trimmed the same way
+                    let (width, cells) = trim(width, height, &cells)
+                        .ok_or_else(|| invalid_data(format!("the pattern for {} is empty", out.item)))?;
+                    Recipe {
+                        ingredients: Ingredients::Shaped {
+                            width,
+                            height: cells.len() / width,
+                            cells,
+                        },
+                        output,
+                    }
+                }
+                RecipeDefinition::Shapeless { ingredients, output: out } => {
+                    let output = output(out)?;
+                    if ingredients.is_empty() || ingredients.len() > MAX_GRID_SIZE * MAX_GRID_SIZE {
+                        return Err(invalid_data(format!("the recipe for {} needs 1 to 9 ingredients", out.item)));
+                    }
+                    Recipe {
+                        ingredients: Ingredients::Shapeless(ingredients.iter().map(|name| item(name)).collect::<io::Result<_>>()?),
+                        output,
+                    }
+                }
+            };
+            recipes.push(recipe);
+        }
+        Ok(Self { recipes })
+    }
+
+    /// the first recipe whose ingredients are in the `width` by `height` grid of `cells`
+    pub fn find(&self, width: usize, height: usize, cells: &[Option<BlockType>]) -> Option<&Recipe> {
+        self.recipes.iter().find(|recipe| recipe.matches(width, height, cells))
+    }
+}
+
+/// A square grid of slots items are crafted in, two wide in the inventory and three wide at a
+/// crafting table.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CraftingGrid {
+    size: usize,
+    slots: Vec<Option<ItemStack>>,
+}
+
+impl CraftingGrid {
+    /// an empty `size` by `size` grid
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            slots: vec![None; size * size],
+        }
+    }
+
+    /// the width and height of the grid
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// the stack in each slot, row by row
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    pub fn slot_mut(&mut self, index: usize) -> &mut Option<ItemStack> {
+        &mut self.slots[index]
+    }
+
+    /// the recipe the items in the grid make
+    pub fn recipe<'a>(&self, book: &'a RecipeBook) -> Option<&'a Recipe> {
+        let cells: Vec<Option<BlockType>> = self.slots.iter().map(|slot| slot.map(|stack| stack.block)).collect();
+        book.find(self.size, self.size, &cells)
+    }
+
+    /// Use up one item from every slot to make the output of the recipe in the grid.
+    pub fn craft(&mut self, book: &RecipeBook) -> Option<ItemStack> {
+        let output = self.recipe(book)?.output;
+        for slot in &mut self.slots {
+            if let Some(stack) = slot {
+                stack.count -= 1;
+                if stack.count == 0 {
+                    *slot = None;
+                }
+            }
+        }
+        Some(output)
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(name: &str) -> Option<BlockType> {
+        let id = ["air", "dirt", "grass", "stone", "wood", "leaves", "planks"].iter().position(|block| *block == name)?;
+        Some(BlockType::from_id(id as u16))
+    }
+
+    fn book() -> RecipeBook {
+        let text = r#"[
+            Shaped(pattern: ["pp", "pp"], key: {'p': "planks"}, output: (item: "stone")),
+            Shaped(pattern: ["pp ", "p  "], key: {'p': "wood"}, output: (item: "leaves", count: 2)),
+            Shaped(pattern: ["sss", "s s", "sss"], key: {'s': "dirt"}, output: (item: "grass", count: 8)),
+            Shapeless(ingredients: ["dirt", "leaves"], output: (item: "grass")),
+        ]"#;
+        RecipeBook::from_ron(text, block).unwrap()
+    }
+
+    /// the blocks of a grid written like a pattern, with `.` for empty cells
+    fn grid(rows: &[&str]) -> Vec<Option<BlockType>> {
+        let names = [('p', "planks"), ('w', "wood"), ('d', "dirt"), ('l', "leaves"), ('s', "stone")];
+        rows.iter()
+            .flat_map(|row| row.chars())
+            .map(|symbol| names.iter().find(|(key, _)| *key == symbol).and_then(|(_, name)| block(name)))
+            .collect()
+    }
+
+    fn output(book: &RecipeBook, size: usize, rows: &[&str]) -> Option<ItemStack> {
+        book.find(size, size, &grid(rows)).map(|recipe| recipe.output)
+    }
+
+    #[test]
+    fn matches_shaped_recipes_anywhere_in_the_grid() {
+        let book = book();
+        let stone = Some(ItemStack { block: block("stone").unwrap(), count: 1 });
+        assert_eq!(output(&book, 2, &["pp", "pp"]), stone);
+        assert_eq!(output(&book, 3, &["pp.", "pp.", "..."]), stone);
+        assert_eq!(output(&book, 3, &["...", ".pp", ".pp"]), stone);
+        assert_eq!(output(&book, 3, &["pp.", "p..", "..."]), None);
+        // anything else in the grid spoils the recipe
+        assert_eq!(output(&book, 3, &["pp.", "pp.", "..d"]), None);
+        assert_eq!(output(&book, 3, &["pp.", ".pp", "..."]), None);
+    }
+
+    #[test]
+    fn matches_mirrored_shaped_recipes() {
+        let book = book();
+        let leaves = Some(ItemStack { block: block("leaves").unwrap(), count: 2 });
+        assert_eq!(output(&book, 2, &["ww", "w."]), leaves);
+        assert_eq!(output(&book, 2, &["ww", ".w"]), leaves);
+        assert_eq!(output(&book, 3, &["...", ".ww", "..w"]), leaves);
+        // mirroring is only left to right
+        assert_eq!(output(&book, 2, &["w.", "ww"]), None);
+    }
+
+    #[test]
+    fn matches_shapeless_recipes_in_any_order() {
+        let book = book();
+        let grass = Some(ItemStack { block: block("grass").unwrap(), count: 1 });
+        assert_eq!(output(&book, 2, &["dl", ".."]), grass);
+        assert_eq!(output(&book, 2, &["..", "ld"]), grass);
+        assert_eq!(output(&book, 3, &["l..", "...", "..d"]), grass);
+        assert_eq!(output(&book, 2, &["dl", "d."]), None);
+        assert_eq!(output(&book, 2, &["d.", ".."]), None);
+    }
+
+    #[test]
+    fn large_recipes_need_a_large_grid() {
+        let book = book();
+        assert_eq!(output(&book, 3, &["ddd", "d.d", "ddd"]).map(|stack| stack.count), Some(8));
+        assert_eq!(output(&book, 2, &["dd", "d."]), None);
+    }
+
+    #[test]
+    fn crafting_uses_one_of_each_ingredient() {
+        let book = book();
+        let planks = block("planks").unwrap();
+        let mut grid = CraftingGrid::new(2);
+        for slot in 0..4 {
+            *grid.slot_mut(slot) = Some(ItemStack { block: planks, count: if slot == 0 { 2 } else { 1 } });
+        }
+        assert_eq!(grid.craft(&book).map(|stack| stack.block), block("stone"));
+        assert_eq!(grid.slots(), [Some(ItemStack { block: planks, count: 1 }), None, None, None]);
+        assert_eq!(grid.craft(&book), None);
+    }
+
+    #[test]
+    fn rejects_broken_recipes() {
+        let broken = [
+            r#"[Shaped(pattern: ["pp", "p"], key: {'p': "planks"}, output: (item: "stone"))]"#,
+            r#"[Shaped(pattern: ["pq"], key: {'p': "planks"}, output: (item: "stone"))]"#,
+            r#"[Shaped(pattern: ["pppp"], key: {'p': "planks"}, output: (item: "stone"))]"#,
+            r#"[Shaped(pattern: ["  "], key: {}, output: (item: "stone"))]"#,
+            r#"[Shapeless(ingredients: ["gold"], output: (item: "stone"))]"#,
+            r#"[Shapeless(ingredients: ["dirt"], output: (item: "stone", count: 0))]"#,
+            r#"[Shapeless(ingredients: [], output: (item: "stone"))]"#,
+        ];
+        for text in broken {
+            assert!(RecipeBook::from_ron(text, block).is_err(), "{text} should be rejected");
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use bevy::{
+    input::InputSystem,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+
+use crate::block_editing::place_block;
+use crate::block_targeting::{target_block, TargetedBlock};
+use crate::block_types::BlockRegistry;
+use crate::chunk_manager::ChunkManager;
+use crate::crafting::{CraftingGrid, RecipeBook, MAX_GRID_SIZE};
+use crate::hotbar_hud::{count_text, item_slot_node, spawn_item_contents, ItemIcons, BORDER_COLOR, SLOT_GAP, SLOT_SIZE};
+use crate::inventory::{click_slot, Inventory, ItemStack, HOTBAR_SLOTS, INVENTORY_SLOTS};
+
+/// opens and closes the inventory screen
+const INVENTORY_KEY: KeyCode = KeyCode::E;
+/// closes any open screen. `bevy_flycam` toggles the cursor grab with the same key, so the press
+/// is used up before it gets to see it
+const CLOSE_KEY: KeyCode = KeyCode::Escape;
+/// opens the crafting table being looked at, the same button blocks are placed with
+const USE_BUTTON: MouseButton = MouseButton::Right;
+/// the block that opens a crafting screen with the large grid
+const CRAFTING_TABLE: &str = "crafting_table";
+/// the width and height of the crafting grid in the inventory screen
+const INVENTORY_GRID_SIZE: usize = 2;
+const BACKDROP_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+const PANEL_COLOR: Color = Color::rgb(0.55, 0.55, 0.55);
+const HOVERED_BORDER_COLOR: Color = Color::WHITE;
+const ARROW_FONT_SIZE: f32 = 32.0;
+
+/// Lets the player move the items in their [`Inventory`] around and craft with them. `E` opens
+/// the inventory with a 2x2 crafting grid, and using a crafting table opens one with a 3x3 grid.
+/// Clicking a slot picks up, puts down or swaps the stack in it, and clicking the output of the
+/// grid crafts it.
+pub struct CraftingScreenPlugin;
+
+impl Plugin for CraftingScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CraftingScreen>()
+            // before the Update systems, bevy_flycam's among them
+            .add_systems(PreUpdate, toggle_inventory_screen.after(InputSystem))
+            .add_systems(
+                Update,
+                (
+                    open_crafting_table.after(target_block).before(place_block),
+                    click_screen_slots,
+                )
+                    .before(show_crafting_screen),
+            )
+            .add_systems(Update, (show_crafting_screen, (update_crafting_screen, highlight_hovered_slots, follow_cursor)).chain());
+    }
+}
+
+/// The open crafting screen and the stack the mouse is carrying on it.
+#[derive(Resource, Default)]
+pub struct CraftingScreen {
+    /// the crafting grid of the open screen, or `None` if no screen is open
+    grid: Option<CraftingGrid>,
+    held: Option<ItemStack>,
+}
+
+impl CraftingScreen {
+    pub fn is_open(&self) -> bool {
+        self.grid.is_some()
+    }
+
+    fn open(&mut self, grid_size: usize, window: &mut Window) {
+        self.grid = Some(CraftingGrid::new(grid_size));
+        window.cursor.grab_mode = CursorGrabMode::None;
+        window.cursor.visible = true;
+    }
+
+    /// Close the screen once the items left in the crafting grid and on the mouse are put back
+    /// into the inventory. If they don't all fit, what is left stays where it was and the screen
+    /// stays open.
+    fn close(&mut self, inventory: &mut Inventory, registry: &BlockRegistry, window: &mut Window) {
+        if !self.put_items_back(inventory, registry) {
+            return;
+        }
+        self.grid = None;
+        window.cursor.grab_mode = CursorGrabMode::Confined;
+        window.cursor.visible = false;
+    }
+
+    /// move as much of the crafting grid and the held stack into the inventory as fits, and
+    /// return whether everything did
+    fn put_items_back(&mut self, inventory: &mut Inventory, registry: &BlockRegistry) -> bool {
+        let CraftingScreen { grid, held } = self;
+        let Some(grid) = grid else {
+            return true;
+        };
+        let mut put_back = |slot: &mut Option<ItemStack>| {
+            if let Some(stack) = slot {
+                stack.count = inventory.add(stack.block, stack.count, registry.get(stack.block).max_stack);
+                if stack.count == 0 {
+                    *slot = None;
+                }
+            }
+        };
+        for index in 0..grid.slots().len() {
+            put_back(grid.slot_mut(index));
+        }
+        put_back(held);
+        grid.slots().iter().all(Option::is_none) && held.is_none()
+    }
+}
+
+/// Marks the root node of the open crafting screen.
+#[derive(Component)]
+struct CraftingScreenRoot;
+
+/// Marks the node that follows the mouse with the stack it is carrying.
+#[derive(Component)]
+struct HeldStackNode;
+
+/// Marks a slot of the crafting screen together with its icon and item count.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+enum ScreenSlot {
+    Inventory(usize),
+    Grid(usize),
+    Output,
+    /// the stack carried by the mouse
+    Held,
+}
+
+fn toggle_inventory_screen(
+    mut keys: ResMut<Input<KeyCode>>,
+    registry: Res<BlockRegistry>,
+    mut screen: ResMut<CraftingScreen>,
+    mut inventory: ResMut<Inventory>,
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let Ok(mut window) = window.get_single_mut() else {
+        return;
+    };
+    if screen.is_open() {
+        let closed = keys.clear_just_pressed(CLOSE_KEY);
+        if closed || keys.just_pressed(INVENTORY_KEY) {
+            screen.close(&mut inventory, &registry, &mut window);
+        }
+    } else if keys.just_pressed(INVENTORY_KEY) && window.cursor.grab_mode != CursorGrabMode::None {
+        screen.open(INVENTORY_GRID_SIZE, &mut window);
+    }
+}
+
+/// Open the large crafting grid when the use button is clicked on a crafting table. This runs
+/// before blocks are placed, and releasing the mouse keeps a block from being placed against
+/// the table as well.
+fn open_crafting_table(
+    buttons: Res<Input<MouseButton>>,
+    targeted: Res<TargetedBlock>,
+    chunks: Res<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut screen: ResMut<CraftingScreen>,
+    mut window: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let Ok(mut window) = window.get_single_mut() else {
+        return;
+    };
+    if screen.is_open() || window.cursor.grab_mode == CursorGrabMode::None || !buttons.just_pressed(USE_BUTTON) {
+        return;
+    }
+    let block = targeted.0.and_then(|hit| chunks.get_block(hit.position));
+    if block.is_some() && block == registry.by_name(CRAFTING_TABLE) {
+        screen.open(MAX_GRID_SIZE, &mut window);
+    }
+}
+
+fn click_screen_slots(
+    interactions: Query<(&Interaction, &ScreenSlot), Changed<Interaction>>,
+    registry: Res<BlockRegistry>,
+    book: Res<RecipeBook>,
+    mut screen: ResMut<CraftingScreen>,
+    mut inventory: ResMut<Inventory>,
+) {
+    let CraftingScreen { grid, held } = &mut *screen;
+    let Some(grid) = grid else {
+        return;
+    };
+    let max_stack = |stack: Option<ItemStack>| stack.map_or(1, |stack| registry.get(stack.block).max_stack);
+    for (interaction, slot) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match *slot {
+            ScreenSlot::Inventory(index) => {
+                let slot = inventory.slot_mut(index);
+                let max_stack = max_stack(held.or(*slot));
+                click_slot(slot, held, max_stack);
+            }
+            ScreenSlot::Grid(index) => {
+                let slot = grid.slot_mut(index);
+                let max_stack = max_stack(held.or(*slot));
+                click_slot(slot, held, max_stack);
+            }
+            // crafted items are picked up, as long as they fit onto what the mouse carries
+            ScreenSlot::Output => {
+                let Some(output) = grid.recipe(&book).map(|recipe| recipe.output) else {
+                    continue;
+                };
+                match held {
+                    None => *held = grid.craft(&book),
+                    Some(stack) if stack.block == output.block && stack.count + output.count <= max_stack(Some(output)) => {
+                        grid.craft(&book);
+                        stack.count += output.count;
+                    }
+                    Some(_) => {}
+                }
+            }
+            ScreenSlot::Held => {}
+        }
+    }
+}
+
+/// spawn the crafting screen when it opens and despawn it when it closes
+fn show_crafting_screen(
+    mut commands: Commands,
+    screen: Res<CraftingScreen>,
+    icons: Res<ItemIcons>,
+    roots: Query<Entity, With<CraftingScreenRoot>>,
+) {
+    match (&screen.grid, roots.get_single()) {
+        (Some(grid), Err(_)) => spawn_crafting_screen(&mut commands, &icons, grid.size()),
+        (None, Ok(root)) => commands.entity(root).despawn_recursive(),
+        _ => {}
+    }
+}
+
+/// Spawn the crafting grid and its output above the inventory, with the hotbar in the bottom
+/// row like it is on the HUD.
+fn spawn_crafting_screen(commands: &mut Commands, icons: &ItemIcons, grid_size: usize) {
+    let backdrop = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        background_color: BACKDROP_COLOR.into(),
+        z_index: ZIndex::Global(1),
+        ..Default::default()
+    };
+    let column = |gap: f32| NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(gap),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let row = || NodeBundle {
+        style: Style {
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(SLOT_GAP),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let slot = |parent: &mut ChildBuilder, slot: ScreenSlot| {
+        parent
+            .spawn((item_slot_node(), Interaction::default(), slot))
+            .with_children(|node| spawn_item_contents(node, icons, slot));
+    };
+
+    commands.spawn((backdrop, CraftingScreenRoot)).with_children(|backdrop| {
+        let mut panel = column(SLOT_GAP * 4.0);
+        panel.style.padding = UiRect::all(Val::Px(SLOT_GAP * 4.0));
+        panel.background_color = PANEL_COLOR.into();
+        backdrop.spawn(panel).with_children(|panel| {
+            panel.spawn(row()).with_children(|crafting| {
+                crafting.spawn(column(SLOT_GAP)).with_children(|grid| {
+                    for y in 0..grid_size {
+                        grid.spawn(row()).with_children(|row| {
+                            for x in 0..grid_size {
+                                slot(row, ScreenSlot::Grid(y * grid_size + x));
+                            }
+                        });
+                    }
+                });
+                let arrow = TextBundle::from_section(
+                    "->",
+                    TextStyle {
+                        font_size: ARROW_FONT_SIZE,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::horizontal(Val::Px(SLOT_SIZE / 2.0)),
+                    ..Default::default()
+                });
+                crafting.spawn(arrow);
+                slot(crafting, ScreenSlot::Output);
+            });
+            panel.spawn(column(SLOT_GAP)).with_children(|inventory| {
+                let rows = (HOTBAR_SLOTS..INVENTORY_SLOTS).step_by(HOTBAR_SLOTS).chain([0]);
+                for first in rows {
+                    inventory.spawn(row()).with_children(|row| {
+                        for index in first..first + HOTBAR_SLOTS {
+                            slot(row, ScreenSlot::Inventory(index));
+                        }
+                    });
+                }
+            });
+        });
+
+        let held = NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Px(SLOT_SIZE),
+                height: Val::Px(SLOT_SIZE),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            z_index: ZIndex::Global(2),
+            ..Default::default()
+        };
+        backdrop
+            .spawn((held, HeldStackNode))
+            .with_children(|node| spawn_item_contents(node, icons, ScreenSlot::Held));
+    });
+}
+
+/// show the items in every slot of the open screen
+fn update_crafting_screen(
+    screen: Res<CraftingScreen>,
+    inventory: Res<Inventory>,
+    registry: Res<BlockRegistry>,
+    book: Res<RecipeBook>,
+    item_icons: Res<ItemIcons>,
+    mut icons: Query<(&ScreenSlot, &mut UiTextureAtlasImage, &mut Visibility)>,
+    mut counts: Query<(&ScreenSlot, &mut Text)>,
+) {
+    let Some(grid) = &screen.grid else {
+        return;
+    };
+    let stack = |slot: ScreenSlot| match slot {
+        ScreenSlot::Inventory(index) => inventory.slots()[index],
+        ScreenSlot::Grid(index) => grid.slots()[index],
+        ScreenSlot::Output => grid.recipe(&book).map(|recipe| recipe.output),
+        ScreenSlot::Held => screen.held,
+    };
+    for (slot, mut icon, mut visibility) in icons.iter_mut() {
+        item_icons.show(stack(*slot), &registry, &mut icon, &mut visibility);
+    }
+    for (slot, mut text) in counts.iter_mut() {
+        text.sections[0].value = count_text(stack(*slot));
+    }
+}
+
+fn highlight_hovered_slots(mut slots: Query<(&ScreenSlot, &Interaction, &mut BorderColor), Changed<Interaction>>) {
+    for (_, interaction, mut border) in slots.iter_mut() {
+        border.0 = match interaction {
+            Interaction::None => BORDER_COLOR,
+            _ => HOVERED_BORDER_COLOR,
+        };
+    }
+}
+
+/// keep the carried stack under the mouse
+fn follow_cursor(window: Query<&Window, With<PrimaryWindow>>, mut held: Query<&mut Style, With<HeldStackNode>>) {
+    let cursor = window.get_single().ok().and_then(|window| window.cursor_position());
+    if let (Some(cursor), Ok(mut style)) = (cursor, held.get_single_mut()) {
+        style.left = Val::Px(cursor.x - SLOT_SIZE / 2.0);
+        style.top = Val::Px(cursor.y - SLOT_SIZE / 2.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closing_keeps_what_does_not_fit_into_the_inventory() {
+        let registry = BlockRegistry::from_assets();
+        let stone = registry.by_name("stone").unwrap();
+        let dirt = registry.by_name("dirt").unwrap();
+        let max_stack = registry.get(stone).max_stack;
+        let mut inventory = Inventory::new(HOTBAR_SLOTS);
+        inventory.add(stone, max_stack * HOTBAR_SLOTS as u16 - 3, max_stack);
+
+        let mut screen = CraftingScreen {
+            grid: Some(CraftingGrid::new(INVENTORY_GRID_SIZE)),
+            held: Some(ItemStack { block: dirt, count: 1 }),
+        };
+        let grid = screen.grid.as_mut().unwrap();
+        *grid.slot_mut(0) = Some(ItemStack { block: stone, count: 2 });
+        *grid.slot_mut(3) = Some(ItemStack { block: stone, count: 2 });
+        let mut window = Window::default();
+        screen.close(&mut inventory, &registry, &mut window);
+        assert!(screen.is_open());
+        assert_eq!(screen.grid.as_ref().unwrap().slots()[3], Some(ItemStack { block: stone, count: 1 }));
+        assert_eq!(screen.held, Some(ItemStack { block: dirt, count: 1 }));
+        assert!(inventory.slots().iter().all(|slot| slot.is_some_and(|stack| stack.count == max_stack)));
+
+        inventory.slot_mut(0).take();
+        inventory.slot_mut(1).take();
+        screen.close(&mut inventory, &registry, &mut window);
+        assert!(!screen.is_open() && screen.held.is_none());
+        assert_eq!(inventory.slots()[0], Some(ItemStack { block: stone, count: 1 }));
+        assert_eq!(inventory.slots()[1], Some(ItemStack { block: dirt, count: 1 }));
+    }
+}
@@ -0,0 +1,244 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::array_texture_material::ArrayTextureMaterial;
+
+/// how many real seconds a whole day lasts, unless the [`WorldTime`] is told otherwise
+const DEFAULT_DAY_LENGTH: f32 = 20.0 * 60.0;
+/// the time of day new worlds start at, early in the morning
+const NEW_WORLD_TIME: f64 = 0.3;
+/// how brightly the sun shines at noon, in lux
+const SUN_ILLUMINANCE: f32 = 100_000.0;
+/// how brightly the moon shines at midnight, in lux
+const MOON_ILLUMINANCE: f32 = 500.0;
+const AMBIENT_COLOR: Color = Color::rgb(0.8, 0.8, 0.8);
+
+/// What the sky looks like at one time of day.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sky {
+    /// the colour the sky is cleared to
+    pub color: [f32; 3],
+    /// the brightness of the [`AmbientLight`]
+    pub ambient: f32,
+    /// how much of the sky light in the chunks reaches the blocks, from 0 to 1
+    pub daylight: f32,
+}
+
+const NIGHT: Sky = Sky {
+    color: [0.01, 0.01, 0.05],
+    ambient: 0.1,
+    daylight: 0.4,
+};
+const TWILIGHT: Sky = Sky {
+    color: [0.9, 0.5, 0.3],
+    ambient: 0.5,
+    daylight: 0.7,
+};
+const DAY: Sky = Sky {
+    color: [0.0, 0.7, 1.0],
+    ambient: 1.0,
+    daylight: 1.0,
+};
+
+/// The sky through the day, by time of day. The sky between two of them is blended from both.
+const SKY_KEYFRAMES: [(f32, Sky); 8] = [
+    (0.0, NIGHT),
+    (0.2, NIGHT),
+    // dawn
+    (0.25, TWILIGHT),
+    (0.3, DAY),
+    (0.7, DAY),
+    // dusk
+    (0.75, TWILIGHT),
+    (0.8, NIGHT),
+    (1.0, NIGHT),
+];
+
+/// Advances a [`WorldTime`], moves the sun and the moon across the sky with it, and changes the
+/// colour of the sky, the ambient light and the daylight on the chunks with the time of day.
+pub struct DayNightPlugin;
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WorldTime>()
+            .add_systems(Startup, spawn_sun_and_moon)
+            .add_systems(Update, (advance_time, update_sky).chain());
+    }
+}
+
+/// The time in the world, counted in days since it was created. A whole day lasts
+/// `day_length` real seconds. The day starts at midnight, the sun rises a quarter of the way
+/// into it, is highest at noon and sets three quarters of the way into it.
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct WorldTime {
+    days: f64,
+    /// how many real seconds a day lasts
+    pub day_length: f32,
+}
+
+impl Default for WorldTime {
+    fn default() -> Self {
+        Self::new(NEW_WORLD_TIME)
+    }
+}
+
+impl WorldTime {
+    /// the time `days` days after the world was created
+    pub fn new(days: f64) -> Self {
+        Self {
+            days,
+            day_length: DEFAULT_DAY_LENGTH,
+        }
+    }
+
+    /// how many days have passed since the world was created
+    pub fn days(&self) -> f64 {
+        self.days
+    }
+
+    /// how far into the current day it is, from 0 at midnight to just below 1
+    pub fn time_of_day(&self) -> f32 {
+        self.days.rem_euclid(1.0) as f32
+    }
+
+    /// let `seconds` real seconds pass
+    pub fn advance(&mut self, seconds: f32) {
+        self.days += seconds as f64 / self.day_length as f64;
+    }
+
+    /// the sky at this time of day
+    pub fn sky(&self) -> Sky {
+        sky_at(self.time_of_day())
+    }
+
+    /// The direction from the world towards the sun. It rises in the east (+x), passes straight
+    /// overhead at noon and sets in the west. The moon is always on the opposite side.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.time_of_day() - 0.25) * TAU;
+        Vec3::new(angle.cos(), angle.sin(), 0.0)
+    }
+}
+
+/// the sky at `time_of_day`, blended between the keyframes around it
+pub fn sky_at(time_of_day: f32) -> Sky {
+    let time_of_day = time_of_day.rem_euclid(1.0);
+    let next = SKY_KEYFRAMES
+        .iter()
+        .position(|(time, _)| *time > time_of_day)
+        .unwrap_or(SKY_KEYFRAMES.len() - 1);
+    let (start, from) = SKY_KEYFRAMES[next - 1];
+    let (end, to) = SKY_KEYFRAMES[next];
+    let t = ((time_of_day - start) / (end - start)).clamp(0.0, 1.0);
+    let lerp = |from: f32, to: f32| from + (to - from) * t;
+    Sky {
+        color: [0, 1, 2].map(|i| lerp(from.color[i], to.color[i])),
+        ambient: lerp(from.ambient, to.ambient),
+        daylight: lerp(from.daylight, to.daylight),
+    }
+}
+
+/// A light crossing the sky with the time of day.
+#[derive(Component, Clone, Copy)]
+enum SkyLight {
+    Sun,
+    Moon,
+}
+
+fn spawn_sun_and_moon(mut commands: Commands) {
+    commands.spawn((DirectionalLightBundle::default(), SkyLight::Sun));
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                color: Color::rgb(0.6, 0.7, 1.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        SkyLight::Moon,
+    ));
+}
+
+fn advance_time(time: Res<Time>, mut world_time: ResMut<WorldTime>) {
+    world_time.advance(time.delta_seconds());
+}
+
+/// Point the sun and the moon at the world from where they are in the sky, and dim them as they
+/// go down. A light below the horizon would shine up through the ground, so it is switched off.
+fn update_sky(
+    world_time: Res<WorldTime>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient: ResMut<AmbientLight>,
+    mut materials: ResMut<Assets<ArrayTextureMaterial>>,
+    mut lights: Query<(&SkyLight, &mut Transform, &mut DirectionalLight)>,
+) {
+    let sky = world_time.sky();
+    let [r, g, b] = sky.color;
+    clear_color.0 = Color::rgb(r, g, b);
+    *ambient = AmbientLight {
+        color: AMBIENT_COLOR,
+        brightness: sky.ambient,
+    };
+
+    let sun_direction = world_time.sun_direction();
+    for (sky_light, mut transform, mut light) in lights.iter_mut() {
+        let (direction, illuminance) = match sky_light {
+            SkyLight::Sun => (sun_direction, SUN_ILLUMINANCE),
+            SkyLight::Moon => (-sun_direction, MOON_ILLUMINANCE),
+        };
+        *transform = Transform::default().looking_to(-direction, Vec3::Z);
+        light.illuminance = illuminance * direction.y.max(0.0);
+    }
+
+    // only touch the materials when the daylight changes, since changing them means uploading
+    // them again
+    let changed: Vec<AssetId<ArrayTextureMaterial>> = materials
+        .iter()
+        .filter(|(_, material)| material.daylight != sky.daylight)
+        .map(|(id, _)| id)
+        .collect();
+    for id in changed {
+        if let Some(material) = materials.get_mut(id) {
+            material.daylight = sky.daylight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sky_follows_the_time_of_day() {
+        assert_eq!(sky_at(0.0), NIGHT);
+        assert_eq!(sky_at(0.25), TWILIGHT);
+        assert_eq!(sky_at(0.5), DAY);
+        assert_eq!(sky_at(0.75), TWILIGHT);
+        assert_eq!(sky_at(0.9), NIGHT);
+        // halfway through dawn
+        let sky = sky_at(0.275);
+        assert!((sky.daylight - (TWILIGHT.daylight + DAY.daylight) / 2.0).abs() < 1e-5);
+        // a day later it looks the same
+        assert_eq!(sky_at(1.5), DAY);
+    }
+
+    #[test]
+    fn time_wraps_around_into_the_next_day() {
+        let mut time = WorldTime::new(0.5);
+        time.day_length = 10.0;
+        time.advance(7.5);
+        assert!((time.days() - 1.25).abs() < 1e-9);
+        assert!((time.time_of_day() - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_sun_is_overhead_at_noon() {
+        let direction = |days| WorldTime::new(days).sun_direction();
+        assert!(direction(0.5).abs_diff_eq(Vec3::Y, 1e-6));
+        assert!(direction(0.0).abs_diff_eq(Vec3::NEG_Y, 1e-6));
+        // rising in the east and setting in the west
+        assert!(direction(0.25).abs_diff_eq(Vec3::X, 1e-6));
+        assert!(direction(0.75).abs_diff_eq(Vec3::NEG_X, 1e-6));
+    }
+}
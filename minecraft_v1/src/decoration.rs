@@ -0,0 +1,425 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::chunk_manager::{Chunk, ChunkManager, Position, CHUNK_DEPTH, CHUNK_WIDTH};
+use crate::chunk_streaming::receive_generated_chunks;
+use crate::terrain_generator::{Biome, TerrainGenerator};
+
+/// the shortest and tallest trunks trees grow
+const TRUNK_HEIGHTS: std::ops::RangeInclusive<isize> = 4..=6;
+/// trunks stand at least this many blocks apart along x or z, so canopies don't grow into trunks
+const TREE_SPACING: isize = 3;
+
+/// Places the blocks that trees reach over the border of their chunk once the chunk they reach
+/// into is loaded. The trees themselves, along with grass and flowers, are placed while chunks
+/// are generated by [`decorate`].
+pub struct DecorationPlugin;
+
+impl Plugin for DecorationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<DeferredWrites>()
+            .add_systems(Update, apply_deferred_writes.after(receive_generated_chunks));
+    }
+}
+
+/// The blocks decorations placed into other chunks than their own, keyed by the chunk
+/// coordinate of the chunk they go into. Each is a world position and the block placed there.
+///
+/// A write is kept until the chunk it goes into is saved with it. Writes are placed into chunks
+/// as they are loaded without editing them, so a freshly generated chunk isn't saved just for
+/// the leaves of its neighbours' trees, and gets them again if it is generated again. The chunks
+/// whose decorations were handed out are remembered, so that doesn't place them twice.
+#[derive(Resource, Default, Debug, PartialEq)]
+pub struct DeferredWrites {
+    /// writes into chunks that don't hold them yet
+    pending: HashMap<Position, Vec<(Position, BlockType)>>,
+    /// writes placed into loaded chunks that haven't been saved since
+    unsaved: HashMap<Position, Vec<(Position, BlockType)>>,
+    /// the chunk coordinates of the chunks whose decorations reaching out of them were deferred
+    decorated: HashSet<Position>,
+}
+
+impl DeferredWrites {
+    /// the writes in `writes`, waiting for their chunks, from the chunks at each of `decorated`
+    pub fn new(writes: impl IntoIterator<Item = (Position, BlockType)>, decorated: impl IntoIterator<Item = Position>) -> Self {
+        let mut deferred = Self {
+            decorated: decorated.into_iter().collect(),
+            ..Default::default()
+        };
+        deferred.wait(writes);
+        deferred
+    }
+
+    /// Wait with placing the `overflow` of the freshly generated chunk at chunk coordinate
+    /// `chunk` until the chunks it reaches into are loaded. The overflow of a chunk that was
+    /// generated before is already waiting or placed, and is dropped.
+    pub fn defer(&mut self, chunk: Position, overflow: Vec<(Position, BlockType)>) {
+        if !overflow.is_empty() && self.decorated.insert(chunk) {
+            self.wait(overflow);
+        }
+    }
+
+    fn wait(&mut self, writes: impl IntoIterator<Item = (Position, BlockType)>) {
+        for (position, block) in writes {
+            self.pending
+                .entry(ChunkManager::chunk_coordinate(position))
+                .or_default()
+                .push((position, block));
+        }
+    }
+
+    /// every write that isn't saved with its chunk yet
+    pub fn iter(&self) -> impl Iterator<Item = (Position, BlockType)> + '_ {
+        self.pending.values().chain(self.unsaved.values()).flatten().copied()
+    }
+
+    /// the chunk coordinates of the chunks whose decorations were deferred
+    pub fn decorated(&self) -> impl Iterator<Item = Position> + '_ {
+        self.decorated.iter().copied()
+    }
+
+    /// the writes that go into the chunk at chunk coordinate `chunk` when it is loaded
+    pub fn writes_into(&self, chunk: Position) -> Vec<(Position, BlockType)> {
+        let (pending, unsaved) = (self.pending.get(&chunk), self.unsaved.get(&chunk));
+        pending.into_iter().chain(unsaved).flatten().copied().collect()
+    }
+
+    /// Place the writes into `chunk` at chunk coordinate `position`, which is about to be loaded,
+    /// without editing it. They are kept until the chunk is saved, see [`DeferredWrites::saved`].
+    pub fn place_into(&mut self, position: Position, chunk: &mut Chunk, registry: &BlockRegistry) {
+        if let Some(pending) = self.pending.remove(&position) {
+            self.unsaved.entry(position).or_default().extend(pending);
+        }
+        if let Some(writes) = self.unsaved.get(&position) {
+            place_writes(chunk, writes, registry);
+        }
+    }
+
+    /// forget the writes placed into the chunk at chunk coordinate `chunk`, which was saved
+    pub fn saved(&mut self, chunk: Position) {
+        self.unsaved.remove(&chunk);
+    }
+
+    /// Place the writes into the chunks that were loaded before them as block edits, which
+    /// saves them with the chunk, and forget about them. Writes only fill blocks that plants
+    /// could grow into, so they never replace the ground or anything built, and writes of blocks
+    /// missing from `registry` are dropped.
+    pub fn apply(&mut self, chunks: &mut ChunkManager, registry: &BlockRegistry) {
+        self.pending.retain(|chunk_position, writes| {
+            if !chunks.chunks.contains_key(chunk_position) {
+                return true;
+            }
+            for (position, block) in writes.drain(..) {
+                let replaceable = chunks.get_block(position).is_some_and(|current| replaceable(registry, current));
+                if replaceable && registry.contains(block) {
+                    chunks.set_block(position, block);
+                }
+            }
+            false
+        });
+    }
+}
+
+/// Place `writes`, which all go into `chunk`, without editing it. Like [`DeferredWrites::apply`],
+/// only blocks plants could grow into are filled.
+pub fn place_writes(chunk: &mut Chunk, writes: &[(Position, BlockType)], registry: &BlockRegistry) {
+    for (position, block) in writes {
+        let local = ChunkManager::local_position(*position);
+        let replaceable = chunk.get(local).is_some_and(|current| replaceable(registry, current));
+        if replaceable && registry.contains(*block) {
+            chunk.set(local, *block);
+        }
+    }
+}
+
+/// whether decorations may grow into `block`: empty blocks and other plants
+fn replaceable(registry: &BlockRegistry, block: BlockType) -> bool {
+    !registry.get(block).solid && !registry.is_fluid(block)
+}
+
+/// How often each kind of decoration grows on a grass block of a biome.
+struct Vegetation {
+    tree: f64,
+    tall_grass: f64,
+    flower: f64,
+}
+
+impl Vegetation {
+    /// the decorations of `biome`, or `None` if nothing grows there
+    fn of(biome: Biome) -> Option<Self> {
+        match biome {
+            Biome::Ocean | Biome::Beach => None,
+            Biome::Plains => Some(Self { tree: 0.003, tall_grass: 0.3, flower: 0.05 }),
+            Biome::Forest => Some(Self { tree: 0.05, tall_grass: 0.15, flower: 0.015 }),
+        }
+    }
+}
+
+/// The block types decorations are made of.
+struct DecorationBlocks {
+    dirt: BlockType,
+    grass: BlockType,
+    wood: BlockType,
+    leaves: BlockType,
+    tall_grass: BlockType,
+    flowers: [BlockType; 2],
+}
+
+impl DecorationBlocks {
+    /// # Panics
+    /// if one of the blocks is missing from the registry
+    fn new(registry: &BlockRegistry) -> Self {
+        let block = |name| {
+            registry
+                .by_name(name)
+                .unwrap_or_else(|| panic!("decorating terrain needs a block called {name}"))
+        };
+        Self {
+            dirt: block("dirt"),
+            grass: block("grass"),
+            wood: block("wood"),
+            leaves: block("leaves"),
+            tall_grass: block("tall_grass"),
+            flowers: [block("dandelion"), block("poppy")],
+        }
+    }
+}
+
+/// Places the blocks of decorations into a chunk that is being generated, collecting the ones
+/// that fall outside of it.
+struct Decorator<'a> {
+    chunk: &'a mut Chunk,
+    origin: Position,
+    registry: &'a BlockRegistry,
+    overflow: Vec<(Position, BlockType)>,
+}
+
+impl Decorator<'_> {
+    /// whether world position `position` lies in the chunk being decorated
+    fn inside(&self, position: Position) -> bool {
+        ChunkManager::chunk_origin(ChunkManager::chunk_coordinate(position)) == self.origin
+    }
+
+    /// Whether a block can be placed at world position `position`. Outside the chunk that is
+    /// only checked once the block is placed into its own chunk.
+    fn fits(&self, position: Position) -> bool {
+        let local = ChunkManager::local_position(position);
+        if !self.inside(position) {
+            return Chunk::contains(local);
+        }
+        self.chunk.get(local).is_some_and(|block| replaceable(self.registry, block))
+    }
+
+    /// place `block` at world position `position` if it fits there
+    fn place(&mut self, position: Position, block: BlockType) {
+        if !self.fits(position) {
+            return;
+        }
+        if self.inside(position) {
+            self.chunk.set(ChunkManager::local_position(position), block);
+        } else {
+            self.overflow.push((position, block));
+        }
+    }
+}
+
+/// the randomness the decorations of the chunk at chunk coordinate `chunk_position` are placed
+/// with, which is the same every time the chunk is generated from `seed`
+fn chunk_rng(seed: u64, chunk_position: Position) -> StdRng {
+    let x = (chunk_position.x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let z = (chunk_position.z as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    StdRng::seed_from_u64(seed ^ x ^ z.rotate_left(32))
+}
+
+/// Grow trees, tall grass and flowers on the grass of the freshly shaped `chunk` at chunk
+/// coordinate `chunk_position`, picked by the biome of every column. Trees near the border of
+/// the chunk reach into the chunks around it, and the blocks they place there are returned by
+/// world position, to be placed once those chunks are loaded. The same seed always decorates
+/// a chunk the same way.
+pub fn decorate(
+    generator: &TerrainGenerator,
+    chunk_position: Position,
+    chunk: &mut Chunk,
+    registry: &BlockRegistry,
+) -> Vec<(Position, BlockType)> {
+    let blocks = DecorationBlocks::new(registry);
+    let mut rng = chunk_rng(generator.seed(), chunk_position);
+    let origin = ChunkManager::chunk_origin(chunk_position);
+    let mut decorator = Decorator { chunk, origin, registry, overflow: Vec::new() };
+    let mut trunks: Vec<Position> = Vec::new();
+
+    for x in 0..CHUNK_WIDTH as isize {
+        for z in 0..CHUNK_DEPTH as isize {
+            let (world_x, world_z) = (origin.x + x, origin.z + z);
+            let ground = Position::new(world_x, generator.height_at(world_x, world_z) as isize - 1, world_z);
+            if decorator.chunk.get(ChunkManager::local_position(ground)) != Some(blocks.grass) {
+                continue;
+            }
+            let Some(vegetation) = Vegetation::of(generator.biome_at(world_x, world_z)) else {
+                continue;
+            };
+            let above = ground + Position::new(0, 1, 0);
+            let roll: f64 = rng.gen();
+            if roll < vegetation.tree {
+                let height = rng.gen_range(TRUNK_HEIGHTS);
+                let crowded = trunks
+                    .iter()
+                    .any(|trunk| (trunk.x - above.x).abs() < TREE_SPACING && (trunk.z - above.z).abs() < TREE_SPACING);
+                if !crowded && grow_tree(&mut decorator, &blocks, &mut rng, above, height) {
+                    trunks.push(above);
+                }
+            } else if roll < vegetation.tree + vegetation.tall_grass {
+                decorator.place(above, blocks.tall_grass);
+            } else if roll < vegetation.tree + vegetation.tall_grass + vegetation.flower {
+                let flower = blocks.flowers[rng.gen_range(0..blocks.flowers.len())];
+                decorator.place(above, flower);
+            }
+        }
+    }
+    decorator.overflow
+}
+
+/// Grow a tree whose trunk, `height` blocks of wood, starts at world position `base`, with dirt
+/// below it and a canopy of leaves around its top. Returns whether the trunk had room to grow.
+fn grow_tree(decorator: &mut Decorator, blocks: &DecorationBlocks, rng: &mut StdRng, base: Position, height: isize) -> bool {
+    // the canopy reaches one block above the trunk
+    let top = base + Position::new(0, height, 0);
+    if !(0..=height).all(|y| decorator.fits(base + Position::new(0, y, 0))) {
+        return false;
+    }
+    decorator.chunk.set(ChunkManager::local_position(base + Position::new(0, -1, 0)), blocks.dirt);
+    for y in 0..height {
+        decorator.place(base + Position::new(0, y, 0), blocks.wood);
+    }
+
+    // two wide layers with some of their corners missing, a narrow one around the top of the
+    // trunk and a plus shape on top
+    for (y, radius) in [(-3, 2), (-2, 2), (-1, 1), (0, 1isize)] {
+        for dx in -radius..=radius {
+            for dz in -radius..=radius {
+                let corner = dx.abs() == radius && dz.abs() == radius;
+                if corner && (y == 0 || (radius == 2 && rng.gen_bool(0.5))) {
+                    continue;
+                }
+                decorator.place(top + Position::new(dx, y, dz), blocks.leaves);
+            }
+        }
+    }
+    true
+}
+
+/// place the deferred writes whose chunks are loaded
+fn apply_deferred_writes(
+    mut writes: ResMut<DeferredWrites>,
+    mut chunks: ResMut<ChunkManager>,
+    registry: Res<BlockRegistry>,
+) {
+    writes.apply(&mut chunks, &registry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the chunk at chunk coordinate `x`, `z` generated from `seed`, and its overflow
+    fn generate(seed: u64, x: isize, z: isize, registry: &BlockRegistry) -> (Chunk, Vec<(Position, BlockType)>) {
+        let generated = TerrainGenerator::new(seed).generate_chunk(Position::new(x, 0, z), registry);
+        (generated.chunk, generated.overflow)
+    }
+
+    #[test]
+    fn decorations_are_the_same_for_the_same_seed() {
+        let registry = BlockRegistry::from_assets();
+        let wood = registry.by_name("wood").unwrap();
+        let mut trees = 0;
+        for x in 0..4 {
+            for z in 0..4 {
+                let (chunk, overflow) = generate(7, x, z, &registry);
+                let (again, again_overflow) = generate(7, x, z, &registry);
+                assert!(chunk == again && overflow == again_overflow, "chunk {x}, {z} changed");
+                trees += chunk.iter().filter(|(_, block)| *block == wood).count();
+            }
+        }
+        assert!(trees > 0, "no trees grew in 16 chunks");
+    }
+
+    #[test]
+    fn trees_reach_into_the_chunks_around_them() {
+        let registry = BlockRegistry::from_assets();
+        let leaves = registry.by_name("leaves").unwrap();
+        let mut overflowed = false;
+        for x in 0..8 {
+            for z in 0..8 {
+                let chunk_position = Position::new(x, 0, z);
+                let (_, overflow) = generate(3, x, z, &registry);
+                for (position, block) in overflow {
+                    overflowed = true;
+                    assert_eq!(block, leaves);
+                    let neighbour = ChunkManager::chunk_coordinate(position);
+                    assert_ne!(neighbour, chunk_position);
+                    assert!((neighbour.x - x).abs() <= 1 && (neighbour.z - z).abs() <= 1);
+                }
+            }
+        }
+        assert!(overflowed, "no tree grew near the border of 64 chunks");
+    }
+
+    #[test]
+    fn deferred_writes_wait_for_their_chunk() {
+        let registry = BlockRegistry::from_assets();
+        let leaves = registry.by_name("leaves").unwrap();
+        let stone = registry.by_name("stone").unwrap();
+        let mut writes = DeferredWrites::new([(Position::new(17, 5, 3), leaves), (Position::new(18, 5, 3), leaves)], []);
+
+        let mut chunks = ChunkManager::default();
+        writes.apply(&mut chunks, &registry);
+        assert_eq!(writes.iter().count(), 2);
+
+        let mut chunk = Chunk::new(Vec3::new(16.0, 0.0, 0.0), BlockType::AIR);
+        chunk.set(Position::new(2, 5, 3), stone);
+        chunks.chunks.insert(Position::new(1, 0, 0), chunk);
+        writes.apply(&mut chunks, &registry);
+        assert_eq!(writes.iter().count(), 0);
+        assert_eq!(chunks.get_block(Position::new(17, 5, 3)), Some(leaves));
+        // the leaves don't replace what is already there
+        assert_eq!(chunks.get_block(Position::new(18, 5, 3)), Some(stone));
+    }
+
+    #[test]
+    fn writes_survive_their_chunk_being_unloaded_unsaved() {
+        let registry = BlockRegistry::from_assets();
+        let leaves = registry.by_name("leaves").unwrap();
+        let source = Position::new(0, 0, 0);
+        let target = Position::new(1, 0, 0);
+        let overflow = vec![(Position::new(17, 70, 3), leaves)];
+        let mut writes = DeferredWrites::default();
+        writes.defer(source, overflow.clone());
+        // the source is unloaded without being saved and generated again
+        writes.defer(source, overflow.clone());
+        assert_eq!(writes.iter().collect::<Vec<_>>(), overflow);
+
+        let mut chunks = ChunkManager::default();
+        for _ in 0..2 {
+            // the target is loaded, and unloaded again without being edited
+            let mut chunk = Chunk::new(Vec3::new(16.0, 0.0, 0.0), BlockType::AIR);
+            writes.place_into(target, &mut chunk, &registry);
+            chunks.chunks.insert(target, chunk);
+            writes.apply(&mut chunks, &registry);
+            assert_eq!(chunks.get_block(Position::new(17, 70, 3)), Some(leaves));
+            assert!(!chunks.chunks[&target].is_dirty());
+            assert!(chunks.edits().is_empty());
+            chunks.chunks.remove(&target);
+        }
+        assert_eq!(writes.iter().collect::<Vec<_>>(), overflow);
+
+        // once the target is saved with them they are forgotten, but not the source they came from
+        writes.saved(target);
+        assert_eq!(writes.iter().count(), 0);
+        writes.defer(source, overflow);
+        assert_eq!(writes.iter().count(), 0);
+    }
+}
@@ -0,0 +1,397 @@
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*};
+
+use crate::block_editing::place_block;
+use crate::block_spawner::ChunkMaterial;
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::chunk_manager::{BlockEdit, ChunkManager, Position};
+use crate::chunk_mesher::{MeshBuilder, Side, MAX_AMBIENT_OCCLUSION};
+use crate::chunk_streaming::{receive_generated_chunks, unload_distant_chunks, ChunkLoaded, StreamingSettings};
+use crate::lighting::Light;
+use crate::world_storage::save_world;
+
+/// how fast falling blocks speed up, in blocks per second squared
+const GRAVITY: f32 = 32.0;
+/// the fastest falling blocks can fall, in blocks per second
+const TERMINAL_VELOCITY: f32 = 78.0;
+
+/// Makes blocks with gravity, like sand, fall down when nothing solid holds them up. A block that
+/// loses its support, is placed in the air, or is generated that way is taken out of its chunk
+/// and turned into a [`FallingBlock`], together with every block with gravity stacked on top of
+/// it. Once a falling block hits something solid it is put back into the chunk where it landed.
+/// Falling blocks aren't saved, so those still in the air when their chunk is unloaded or the
+/// game is closed are put back where they are.
+pub struct FallingBlocksPlugin;
+
+impl Plugin for FallingBlocksPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(
+                Update,
+                (fall, topple_edited_blocks, topple_loaded_blocks)
+                    .chain()
+                    .after(place_block)
+                    .after(receive_generated_chunks),
+            )
+            .add_systems(Update, settle_blocks_in_unloading_chunks.before(unload_distant_chunks))
+            .add_systems(Last, settle_all_blocks.before(save_world).run_if(on_event::<AppExit>()));
+    }
+}
+
+/// A block with gravity falling down through the world. The entity's translation is the
+/// minimum corner of the block, which stays lined up with the blocks around it along x and z.
+#[derive(Component)]
+pub struct FallingBlock {
+    pub block: BlockType,
+    /// how fast the block is falling, in blocks per second
+    velocity: f32,
+}
+
+/// Whether the block at `position` holds up blocks on top of it. Only solid blocks do, except
+/// that blocks on the bottom of the world or above a chunk that isn't loaded stay where they are.
+fn holds_up(chunks: &ChunkManager, registry: &BlockRegistry, position: Position) -> bool {
+    chunks.get_block(position).is_none_or(|block| registry.get(block).solid)
+}
+
+/// whether the block at `position` is held up by the block below it
+fn supported(chunks: &ChunkManager, registry: &BlockRegistry, position: Position) -> bool {
+    holds_up(chunks, registry, position + Side::Bottom.offset())
+}
+
+/// Take every block with gravity among `positions` that isn't held up out of the world, along
+/// with the blocks with gravity stacked on top of each of them, which lose their support with
+/// it. Returns the blocks that start falling and where they were, lowest first in every column.
+pub fn topple(
+    chunks: &mut ChunkManager,
+    registry: &BlockRegistry,
+    positions: impl IntoIterator<Item = Position>,
+) -> Vec<(Position, BlockType)> {
+    let mut toppled = Vec::new();
+    for position in positions {
+        let mut position = position;
+        while let Some(block) = chunks.get_block(position) {
+            if !registry.has_gravity(block) || supported(chunks, registry, position) {
+                break;
+            }
+            chunks.remove_block(position);
+            toppled.push((position, block));
+            position = position + Side::Top.offset();
+        }
+    }
+    toppled
+}
+
+/// the blocks that may have lost their support through `edits`: the edited blocks themselves,
+/// and the blocks on top of them
+fn unsupported_by(edits: &[BlockEdit]) -> Vec<Position> {
+    edits
+        .iter()
+        .flat_map(|edit| [edit.position, edit.position + Side::Top.offset()])
+        .collect()
+}
+
+/// Let `falling`, whose minimum corner is at `corner`, fall for `seconds`. Returns where the
+/// block landed if it hit something solid on the way, and `None` if it is still falling.
+fn fall_step(
+    chunks: &ChunkManager,
+    registry: &BlockRegistry,
+    falling: &mut FallingBlock,
+    corner: &mut Vec3,
+    seconds: f32,
+) -> Option<Position> {
+    falling.velocity = (falling.velocity + GRAVITY * seconds).min(TERMINAL_VELOCITY);
+    let bottom = corner.y - falling.velocity * seconds;
+    let (x, z) = (corner.x.floor() as isize, corner.z.floor() as isize);
+    // every block the falling block moves into on the way down, from the top
+    for y in (bottom.floor() as isize..corner.y.ceil() as isize).rev() {
+        let position = Position::new(x, y, z);
+        if holds_up(chunks, registry, position) {
+            return Some(position + Side::Top.offset());
+        }
+    }
+    corner.y = bottom;
+    None
+}
+
+/// Where a block falling with its minimum corner at `corner` is put back into the world if it
+/// can't finish its fall: the block it is in, or the first one above it that isn't solid in case
+/// something was placed there since. `None` if there is no such block in a loaded chunk.
+fn resting_place(chunks: &ChunkManager, registry: &BlockRegistry, corner: Vec3) -> Option<Position> {
+    let mut position = Position::from(corner.floor());
+    while registry.get(chunks.get_block(position)?).solid {
+        position = position + Side::Top.offset();
+    }
+    Some(position)
+}
+
+/// Put the falling block on `entity` back into the world where it is, see [`resting_place`].
+/// The entity is only despawned once the block is back in its chunk.
+fn settle(commands: &mut Commands, chunks: &mut ChunkManager, registry: &BlockRegistry, entity: Entity, falling: &FallingBlock, corner: Vec3) {
+    let placed = resting_place(chunks, registry, corner).and_then(|position| chunks.set_block(position, falling.block));
+    if placed.is_some() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Spawns the entities of blocks that start falling.
+#[derive(SystemParam)]
+struct FallingBlockSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    material: Res<'w, ChunkMaterial>,
+}
+
+impl FallingBlockSpawner<'_, '_> {
+    /// turn `block`, which was taken out of the chunk at `position`, into a [`FallingBlock`]
+    fn spawn(&mut self, registry: &BlockRegistry, position: Position, block: BlockType) {
+        let mut builder = MeshBuilder::default();
+        if registry.is_visible(block) {
+            for side in Side::ALL {
+                let texture = registry.face_texture(block, side);
+                let occlusion = [MAX_AMBIENT_OCCLUSION; 4];
+                builder.push_quad(side, Vec3::ZERO, Vec3::ONE, texture, Light::DAYLIGHT, occlusion);
+            }
+        }
+        let mesh = self.meshes.add(builder.build());
+        let transform = Transform::from_translation(position.into());
+        let entity = self.material.spawn(&mut self.commands, registry.render_mode(block), mesh, transform);
+        self.commands.entity(entity).insert(FallingBlock { block, velocity: 0.0 });
+    }
+}
+
+/// move the falling blocks down, and put the ones that landed back into the world
+fn fall(
+    mut commands: Commands,
+    time: Res<Time>,
+    registry: Res<BlockRegistry>,
+    mut chunks: ResMut<ChunkManager>,
+    mut falling: Query<(Entity, &mut FallingBlock, &mut Transform)>,
+) {
+    for (entity, mut block, mut transform) in falling.iter_mut() {
+        // blocks above chunks that aren't loaded wait in the air for them
+        if !chunks.chunks.contains_key(&ChunkManager::chunk_coordinate(Position::from(transform.translation.floor()))) {
+            continue;
+        }
+        if let Some(landed) = fall_step(&chunks, &registry, &mut block, &mut transform.translation, time.delta_seconds()) {
+            // a block that can't be put down stays where it is and tries again next frame
+            if chunks.set_block(landed, block.block).is_some() {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+/// put the falling blocks in chunks that are about to be unloaded back into them, so they are
+/// saved with their chunk
+fn settle_blocks_in_unloading_chunks(
+    mut commands: Commands,
+    settings: Res<StreamingSettings>,
+    registry: Res<BlockRegistry>,
+    mut chunks: ResMut<ChunkManager>,
+    falling: Query<(Entity, &FallingBlock, &Transform)>,
+    camera: Query<&GlobalTransform, With<Camera3d>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    for (entity, block, transform) in falling.iter() {
+        let chunk = ChunkManager::chunk_coordinate(Position::from(transform.translation.floor()));
+        if !settings.keeps_loaded(camera, chunk) {
+            settle(&mut commands, &mut chunks, &registry, entity, block, transform.translation);
+        }
+    }
+}
+
+/// put every falling block back into the world before it is saved for the last time
+fn settle_all_blocks(
+    mut commands: Commands,
+    registry: Res<BlockRegistry>,
+    mut chunks: ResMut<ChunkManager>,
+    falling: Query<(Entity, &FallingBlock, &Transform)>,
+) {
+    for (entity, block, transform) in falling.iter() {
+        settle(&mut commands, &mut chunks, &registry, entity, block, transform.translation);
+    }
+}
+
+/// start blocks falling that lost their support through the edits made this frame
+fn topple_edited_blocks(mut chunks: ResMut<ChunkManager>, registry: Res<BlockRegistry>, mut spawner: FallingBlockSpawner) {
+    let positions = unsupported_by(chunks.edits());
+    for (position, block) in topple(&mut chunks, &registry, positions) {
+        spawner.spawn(&registry, position, block);
+    }
+}
+
+/// start blocks falling that were generated or saved without anything holding them up
+fn topple_loaded_blocks(
+    mut loaded: EventReader<ChunkLoaded>,
+    mut chunks: ResMut<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut spawner: FallingBlockSpawner,
+) {
+    for ChunkLoaded(chunk_position) in loaded.read() {
+        let Some(chunk) = chunks.chunks.get(chunk_position) else {
+            continue;
+        };
+        if !chunk.palette().iter().any(|block| registry.has_gravity(*block)) {
+            continue;
+        }
+        let origin = ChunkManager::chunk_origin(*chunk_position);
+        let positions: Vec<Position> = chunk
+            .iter()
+            .filter(|(_, block)| registry.has_gravity(*block))
+            .map(|(local, _)| origin + local)
+            .collect();
+        for (position, block) in topple(&mut chunks, &registry, positions) {
+            spawner.spawn(&registry, position, block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_manager::Chunk;
+
+    struct Blocks {
+        registry: BlockRegistry,
+        stone: BlockType,
+        sand: BlockType,
+        water: BlockType,
+    }
+
+    impl Blocks {
+        fn new() -> Self {
+            let registry = BlockRegistry::from_assets();
+            Self {
+                stone: registry.by_name("stone").unwrap(),
+                sand: registry.by_name("sand").unwrap(),
+                water: registry.by_name("water").unwrap(),
+                registry,
+            }
+        }
+    }
+
+    /// a single chunk of air with a stone floor at y = 0
+    fn floor(blocks: &Blocks) -> ChunkManager {
+        let mut chunk = Chunk::new(Vec3::ZERO, BlockType::AIR);
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(Position::new(x, 0, z), blocks.stone);
+            }
+        }
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(Position::new(0, 0, 0), chunk);
+        chunks
+    }
+
+    /// let every block in `toppled` fall until it lands, one after another from the bottom up
+    /// like they would in the world, and return where they landed
+    fn land(chunks: &mut ChunkManager, blocks: &Blocks, toppled: Vec<(Position, BlockType)>) -> Vec<Position> {
+        toppled
+            .into_iter()
+            .map(|(position, block)| {
+                let mut falling = FallingBlock { block, velocity: 0.0 };
+                let mut corner = Vec3::from(position);
+                loop {
+                    if let Some(landed) = fall_step(chunks, &blocks.registry, &mut falling, &mut corner, 1.0 / 60.0) {
+                        chunks.set_block(landed, block);
+                        return landed;
+                    }
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_stack_of_sand_collapses_together() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        // three sand blocks on a stone pillar, with stone on top of them
+        chunks.set_block(Position::new(5, 1, 5), blocks.stone);
+        for y in 2..5 {
+            chunks.set_block(Position::new(5, y, 5), blocks.sand);
+        }
+        chunks.set_block(Position::new(5, 5, 5), blocks.stone);
+
+        // nothing falls while the pillar is there
+        assert!(topple(&mut chunks, &blocks.registry, [Position::new(5, 2, 5)]).is_empty());
+        chunks.remove_block(Position::new(5, 1, 5));
+        let removed = unsupported_by(&chunks.edits()[chunks.edits().len() - 1..]);
+        let toppled = topple(&mut chunks, &blocks.registry, removed);
+        assert_eq!(toppled.iter().map(|(position, _)| position.y).collect::<Vec<_>>(), [2, 3, 4]);
+        for y in 2..5 {
+            assert_eq!(chunks.get_block(Position::new(5, y, 5)), Some(BlockType::AIR));
+        }
+
+        // they pile up on the floor in the same order
+        assert_eq!(land(&mut chunks, &blocks, toppled), [Position::new(5, 1, 5), Position::new(5, 2, 5), Position::new(5, 3, 5)]);
+        // and the stone on top doesn't fall
+        assert_eq!(chunks.get_block(Position::new(5, 5, 5)), Some(blocks.stone));
+    }
+
+    #[test]
+    fn sand_falls_through_water() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        chunks.set_block(Position::new(5, 1, 5), blocks.water);
+        chunks.set_block(Position::new(5, 2, 5), blocks.water);
+        chunks.set_block(Position::new(5, 6, 5), blocks.sand);
+
+        let placed = unsupported_by(chunks.edits());
+        let toppled = topple(&mut chunks, &blocks.registry, placed);
+        assert_eq!(toppled, [(Position::new(5, 6, 5), blocks.sand)]);
+        assert_eq!(land(&mut chunks, &blocks, toppled), [Position::new(5, 1, 5)]);
+        assert_eq!(chunks.get_block(Position::new(5, 1, 5)), Some(blocks.sand));
+    }
+
+    #[test]
+    fn blocks_in_the_air_are_put_back_where_they_are() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        assert_eq!(resting_place(&chunks, &blocks.registry, Vec3::new(5.0, 6.5, 5.0)), Some(Position::new(5, 6, 5)));
+        // above whatever was put where the block is
+        chunks.set_block(Position::new(5, 6, 5), blocks.stone);
+        assert_eq!(resting_place(&chunks, &blocks.registry, Vec3::new(5.0, 6.5, 5.0)), Some(Position::new(5, 7, 5)));
+        // but not outside of the loaded chunks
+        assert_eq!(resting_place(&chunks, &blocks.registry, Vec3::new(20.0, 6.5, 5.0)), None);
+    }
+
+    #[test]
+    fn falling_blocks_are_only_despawned_once_they_are_back_in_the_world() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let blocks = Blocks::new();
+        let mut world = World::new();
+        world.insert_resource(floor(&blocks));
+        world.insert_resource(blocks.registry.clone());
+        let spawn = |world: &mut World, corner: Vec3| {
+            world
+                .spawn((FallingBlock { block: blocks.sand, velocity: 0.0 }, Transform::from_translation(corner)))
+                .id()
+        };
+        let in_the_air = spawn(&mut world, Vec3::new(5.0, 6.5, 5.0));
+        // over a chunk that isn't loaded, as when its chunk was unloaded before it
+        let over_nothing = spawn(&mut world, Vec3::new(20.0, 6.5, 5.0));
+
+        world.run_system_once(settle_all_blocks);
+        assert!(world.get_entity(in_the_air).is_none());
+        assert!(world.get_entity(over_nothing).is_some());
+        let chunks = world.resource::<ChunkManager>();
+        assert_eq!(chunks.get_block(Position::new(5, 6, 5)), Some(blocks.sand));
+        assert!(chunks.chunks[&Position::new(0, 0, 0)].is_dirty());
+    }
+
+    #[test]
+    fn fast_blocks_do_not_fall_through_the_floor() {
+        let blocks = Blocks::new();
+        let chunks = floor(&blocks);
+        let mut falling = FallingBlock { block: blocks.sand, velocity: TERMINAL_VELOCITY };
+        let mut corner = Vec3::new(3.0, 10.0, 3.0);
+        // a whole second at terminal velocity would take it far below the floor
+        assert_eq!(fall_step(&chunks, &blocks.registry, &mut falling, &mut corner, 1.0), Some(Position::new(3, 1, 3)));
+        // while a short step only moves it down
+        let mut falling = FallingBlock { block: blocks.sand, velocity: 0.0 };
+        assert_eq!(fall_step(&chunks, &blocks.registry, &mut falling, &mut corner, 0.1), None);
+        assert!(corner.y < 10.0);
+    }
+}
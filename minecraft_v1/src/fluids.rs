@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::chunk_manager::{send_remesh_events, BlockEdit, ChunkManager, Position};
+use crate::chunk_mesher::Side;
+
+/// the level of a source block, which never runs dry
+pub const SOURCE_LEVEL: u8 = 8;
+/// the level of fluid falling down from the block above it
+const FALLING_LEVEL: u8 = SOURCE_LEVEL - 1;
+/// how many seconds pass between two steps of the flow
+const FLOW_INTERVAL: f32 = 0.25;
+/// the four sides fluid spreads out to
+const HORIZONTAL: [Side; 4] = [Side::Forward, Side::Back, Side::Left, Side::Right];
+
+/// Lets fluid blocks like water flow. Sources keep their level forever, and fluid flows out of
+/// them in fixed steps: straight down into empty blocks first, and once it can't go any lower,
+/// sideways onto the blocks around it, one level lower with every block until it runs out.
+/// Flowing fluid that isn't fed by a source anymore drains away the same way it came.
+///
+/// Only blocks around a block edit are updated, so fluid settles once nothing flows anymore and
+/// costs nothing until a block next to it changes.
+pub struct FluidsPlugin;
+
+impl Plugin for FluidsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FlowTimer>()
+            .init_resource::<FluidUpdates>()
+            .add_systems(FixedUpdate, flow_fluids)
+            .add_systems(PostUpdate, schedule_fluid_updates.before(send_remesh_events));
+    }
+}
+
+/// Counts the fixed timesteps up to the next step of the flow, which is slower than the fixed
+/// timestep of the rest of the game.
+#[derive(Resource)]
+struct FlowTimer(Timer);
+
+impl Default for FlowTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(FLOW_INTERVAL, TimerMode::Repeating))
+    }
+}
+
+/// The world positions of the blocks to update on the next step of the flow.
+#[derive(Resource, Default)]
+struct FluidUpdates(HashSet<Position>);
+
+/// the level of `block`, which has the flow level `flow_level`, or `None` if it isn't a fluid
+pub fn fluid_level(registry: &BlockRegistry, block: BlockType, flow_level: Option<u8>) -> Option<u8> {
+    registry.is_fluid(block).then(|| flow_level.unwrap_or(SOURCE_LEVEL))
+}
+
+/// how high the surface of fluid at `level` lies above the bottom of its block, a little below
+/// the top even for sources
+pub fn surface_height(level: u8) -> f32 {
+    level as f32 / (SOURCE_LEVEL + 1) as f32
+}
+
+/// whether fluid can flow into `block`, washing it away
+fn can_flow_into(registry: &BlockRegistry, block: BlockType) -> bool {
+    !registry.get(block).solid && !registry.is_fluid(block)
+}
+
+/// Whether `fluid` at `position` flows down instead of spreading out, because the block below
+/// is empty or more of the fluid that is still flowing. Fluid on top of a source spreads out
+/// over it like over the ground.
+fn falls(chunks: &ChunkManager, registry: &BlockRegistry, position: Position, fluid: BlockType) -> bool {
+    let below = position + Side::Bottom.offset();
+    chunks.get_block(below).is_some_and(|block| {
+        can_flow_into(registry, block) || (block == fluid && chunks.flow_level(below).is_some())
+    })
+}
+
+/// What a block turns into on the next step of the flow.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Flow {
+    /// the block fills up with the fluid at the level
+    Fill(BlockType, u8),
+    /// the fluid in the block runs dry
+    Drain,
+}
+
+/// The level `fluid` flows into the block at `position` with, 0 if none reaches it. Fluid above
+/// the block falls into it, while fluid beside it only spreads into it if there is nowhere
+/// lower for it to go.
+fn inflow(chunks: &ChunkManager, registry: &BlockRegistry, position: Position, fluid: BlockType) -> u8 {
+    if chunks.get_block(position + Side::Top.offset()) == Some(fluid) {
+        return FALLING_LEVEL;
+    }
+    HORIZONTAL
+        .into_iter()
+        .filter_map(|side| {
+            let neighbour = position + side.offset();
+            if chunks.get_block(neighbour) != Some(fluid) {
+                return None;
+            }
+            if falls(chunks, registry, neighbour, fluid) {
+                return None;
+            }
+            let level = fluid_level(registry, fluid, chunks.flow_level(neighbour))?;
+            Some(level - 1)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// what the block at `position` turns into on the next step of the flow, or `None` if it stays
+/// as it is
+fn next_flow(chunks: &ChunkManager, registry: &BlockRegistry, position: Position) -> Option<Flow> {
+    let block = chunks.get_block(position)?;
+    if registry.is_fluid(block) {
+        // sources never change
+        let current = chunks.flow_level(position)?;
+        return match inflow(chunks, registry, position, block) {
+            0 => Some(Flow::Drain),
+            level if level != current => Some(Flow::Fill(block, level)),
+            _ => None,
+        };
+    }
+    if !can_flow_into(registry, block) {
+        return None;
+    }
+    // the strongest of the fluids next to the block fills it
+    Side::ALL
+        .into_iter()
+        .filter(|side| *side != Side::Bottom)
+        .filter_map(|side| chunks.get_block(position + side.offset()))
+        .filter(|neighbour| registry.is_fluid(*neighbour))
+        .map(|fluid| (inflow(chunks, registry, position, fluid), fluid))
+        .max_by_key(|(level, _)| *level)
+        .filter(|(level, _)| *level > 0)
+        .map(|(level, fluid)| Flow::Fill(fluid, level))
+}
+
+/// Take one step of the flow at each of `positions`. Every block works out what it turns into
+/// from the blocks as they were before the step, so the order of `positions` doesn't matter.
+pub fn flow(chunks: &mut ChunkManager, registry: &BlockRegistry, positions: impl IntoIterator<Item = Position>) {
+    let changes: Vec<(Position, Flow)> = positions
+        .into_iter()
+        .filter_map(|position| Some((position, next_flow(chunks, registry, position)?)))
+        .collect();
+    for (position, change) in changes {
+        match change {
+            Flow::Fill(fluid, level) => {
+                chunks.set_block(position, fluid);
+                chunks.set_flow_level(position, Some(level));
+            }
+            Flow::Drain => {
+                chunks.remove_block(position);
+            }
+        }
+    }
+}
+
+/// the blocks whose flow may change because of `edits`: the edited blocks and the blocks
+/// around them
+pub fn affected_by(edits: &[BlockEdit]) -> HashSet<Position> {
+    edits
+        .iter()
+        .flat_map(|edit| {
+            Side::ALL
+                .map(|side| edit.position + side.offset())
+                .into_iter()
+                .chain([edit.position])
+        })
+        .collect()
+}
+
+/// take one step of the flow at every block that was scheduled for it, every [`FLOW_INTERVAL`]
+/// seconds
+fn flow_fluids(
+    time: Res<Time>,
+    mut timer: ResMut<FlowTimer>,
+    mut chunks: ResMut<ChunkManager>,
+    registry: Res<BlockRegistry>,
+    mut updates: ResMut<FluidUpdates>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let positions = std::mem::take(&mut updates.0);
+    flow(&mut chunks, &registry, positions);
+}
+
+/// Update the blocks around every block edited this frame on the next step. That includes the
+/// edits made by the flow itself, which keeps it going until it settles.
+fn schedule_fluid_updates(chunks: Res<ChunkManager>, mut updates: ResMut<FluidUpdates>) {
+    updates.0.extend(affected_by(chunks.edits()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_manager::Chunk;
+
+    struct Blocks {
+        registry: BlockRegistry,
+        stone: BlockType,
+        water: BlockType,
+    }
+
+    impl Blocks {
+        fn new() -> Self {
+            let registry = BlockRegistry::from_assets();
+            Self {
+                stone: registry.by_name("stone").unwrap(),
+                water: registry.by_name("water").unwrap(),
+                registry,
+            }
+        }
+    }
+
+    /// a single chunk of air with a stone floor at y = 0
+    fn floor(blocks: &Blocks) -> ChunkManager {
+        let mut chunk = Chunk::new(Vec3::ZERO, BlockType::AIR);
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set(Position::new(x, 0, z), blocks.stone);
+            }
+        }
+        let mut chunks = ChunkManager::default();
+        chunks.chunks.insert(Position::new(0, 0, 0), chunk);
+        chunks
+    }
+
+    /// Take `steps` steps of the flow, starting with the blocks around `changed`, and every step
+    /// after that with the blocks around what the step before changed, like the plugin does.
+    fn run(chunks: &mut ChunkManager, blocks: &Blocks, changed: Position, steps: usize) {
+        let mut seen = chunks.edits().len();
+        let mut positions = affected_by(&[BlockEdit { position: changed, previous: BlockType::AIR, block: BlockType::AIR }]);
+        for _ in 0..steps {
+            flow(chunks, &blocks.registry, positions);
+            positions = affected_by(&chunks.edits()[seen..]);
+            seen = chunks.edits().len();
+        }
+    }
+
+    /// the level of the fluid at `x`, `y`, `z`, 0 if there is none
+    fn level(chunks: &ChunkManager, blocks: &Blocks, x: isize, y: isize, z: isize) -> u8 {
+        let position = Position::new(x, y, z);
+        let block = chunks.get_block(position).unwrap();
+        fluid_level(&blocks.registry, block, chunks.flow_level(position)).unwrap_or(0)
+    }
+
+    #[test]
+    fn water_spreads_out_one_level_lower_per_block() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        chunks.set_block(Position::new(8, 1, 8), blocks.water);
+        run(&mut chunks, &blocks, Position::new(8, 1, 8), 20);
+
+        let row: Vec<u8> = (0..16).map(|x| level(&chunks, &blocks, x, 1, 8)).collect();
+        assert_eq!(row, [0, 1, 2, 3, 4, 5, 6, 7, 8, 7, 6, 5, 4, 3, 2, 1]);
+        // diagonally, it takes two steps to get around the corner
+        assert_eq!(level(&chunks, &blocks, 9, 1, 9), 6);
+        // and it stays on the floor
+        assert_eq!(level(&chunks, &blocks, 9, 2, 8), 0);
+    }
+
+    #[test]
+    fn water_falls_before_it_spreads() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        // a ledge at y = 4 with the source on its edge
+        chunks.set_block(Position::new(8, 4, 8), blocks.stone);
+        chunks.set_block(Position::new(8, 5, 8), blocks.water);
+        run(&mut chunks, &blocks, Position::new(8, 5, 8), 20);
+
+        // it spreads over the ledge, but falls off it as soon as it is over the edge
+        assert_eq!(level(&chunks, &blocks, 9, 5, 8), 7);
+        assert_eq!(level(&chunks, &blocks, 10, 5, 8), 0);
+        for y in 1..5 {
+            assert_eq!(level(&chunks, &blocks, 9, y, 8), FALLING_LEVEL, "y = {y}");
+        }
+        // and spreads out again on the floor
+        assert_eq!(level(&chunks, &blocks, 10, 1, 8), FALLING_LEVEL - 1);
+        assert_eq!(level(&chunks, &blocks, 9, 2, 9), 0);
+    }
+
+    #[test]
+    fn water_drains_once_its_source_is_gone() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        chunks.set_block(Position::new(8, 1, 8), blocks.water);
+        run(&mut chunks, &blocks, Position::new(8, 1, 8), 20);
+        assert_eq!(level(&chunks, &blocks, 12, 1, 8), 4);
+
+        chunks.remove_block(Position::new(8, 1, 8));
+        run(&mut chunks, &blocks, Position::new(8, 1, 8), 40);
+        let wet = chunks.chunks[&Position::new(0, 0, 0)].iter().any(|(_, block)| block == blocks.water);
+        assert!(!wet);
+    }
+
+    #[test]
+    fn flow_stops_at_walls() {
+        let blocks = Blocks::new();
+        let mut chunks = floor(&blocks);
+        chunks.set_block(Position::new(10, 1, 8), blocks.stone);
+        chunks.set_block(Position::new(8, 1, 8), blocks.water);
+        run(&mut chunks, &blocks, Position::new(8, 1, 8), 20);
+
+        assert_eq!(level(&chunks, &blocks, 9, 1, 8), 7);
+        assert_eq!(level(&chunks, &blocks, 10, 1, 8), 0);
+        // it only gets behind the wall the long way round
+        assert_eq!(level(&chunks, &blocks, 11, 1, 8), 3);
+    }
+}
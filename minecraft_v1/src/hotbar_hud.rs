@@ -0,0 +1,189 @@
+use bevy::{prelude::*, sprite::TextureAtlas as IconAtlas};
+
+use crate::block_types::BlockRegistry;
+use crate::chunk_mesher::Side;
+use crate::inventory::{Inventory, ItemStack, HOTBAR_SLOTS};
+use crate::load_texture_atlas::{load_texture_atlas, TextureAtlas};
+
+/// the width and height of an item slot, in pixels
+pub const SLOT_SIZE: f32 = 48.0;
+const SLOT_BORDER: f32 = 3.0;
+pub const SLOT_GAP: f32 = 4.0;
+const ICON_SIZE: f32 = 34.0;
+const COUNT_FONT_SIZE: f32 = 16.0;
+const SLOT_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.6);
+pub const BORDER_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+const SELECTED_BORDER_COLOR: Color = Color::WHITE;
+/// the face of a block whose texture is the block's icon
+const ICON_SIDE: Side = Side::Forward;
+
+/// Shows the hotbar of the [`Inventory`] at the bottom of the screen, with an icon cut from the
+/// [`TextureAtlas`] for every item. The [`ItemIcons`] and item slots are shared with the other
+/// screens showing items.
+pub struct HotbarHudPlugin;
+
+impl Plugin for HotbarHudPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, (create_item_icons.after(load_texture_atlas), apply_deferred, spawn_hotbar).chain())
+            .add_systems(Update, update_hotbar.run_if(resource_changed::<Inventory>()));
+    }
+}
+
+/// The icon of every item, cut from the texture atlas in the order of its tiles, row by row.
+#[derive(Resource)]
+pub struct ItemIcons {
+    atlas: Handle<IconAtlas>,
+    /// the number of tiles in a row of the texture atlas
+    columns: u32,
+}
+
+impl ItemIcons {
+    /// show the icon of the item in `stack` on an icon spawned by [`spawn_item_contents`], or
+    /// hide the icon if there is nothing to show
+    pub fn show(
+        &self,
+        stack: Option<ItemStack>,
+        registry: &BlockRegistry,
+        icon: &mut UiTextureAtlasImage,
+        visibility: &mut Visibility,
+    ) {
+        match stack.filter(|stack| registry.is_visible(stack.block)) {
+            Some(stack) => {
+                let (column, row) = registry.face_tile(stack.block, ICON_SIDE);
+                icon.index = (row * self.columns + column) as usize;
+                *visibility = Visibility::Inherited;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+/// the item count shown on a slot holding `stack`, which is left out for single items
+pub fn count_text(stack: Option<ItemStack>) -> String {
+    match stack {
+        Some(stack) if stack.count > 1 => stack.count.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Marks the slot, icon and item count of the hotbar slot with this index.
+#[derive(Component, Clone, Copy)]
+struct HotbarSlot(usize);
+
+fn create_item_icons(
+    mut commands: Commands,
+    texture_atlas: Res<TextureAtlas>,
+    images: Res<Assets<Image>>,
+    mut icon_atlases: ResMut<Assets<IconAtlas>>,
+) {
+    let handle = texture_atlas.handle.clone().expect("the texture atlas is loaded at startup");
+    let size = images.get(&handle).expect("the texture atlas is loaded at startup").size_f32();
+    let layout = texture_atlas.layout;
+    let mut icons = IconAtlas::new_empty(handle, size);
+    for row in 0..layout.rows {
+        for column in 0..layout.columns {
+            let uvs = layout.tile_rect((column, row));
+            icons.add_texture(Rect::from_corners(uvs.min * size, uvs.max * size));
+        }
+    }
+    commands.insert_resource(ItemIcons {
+        atlas: icon_atlases.add(icons),
+        columns: layout.columns,
+    });
+}
+
+/// an empty square slot for one stack of items
+pub fn item_slot_node() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            width: Val::Px(SLOT_SIZE),
+            height: Val::Px(SLOT_SIZE),
+            border: UiRect::all(Val::Px(SLOT_BORDER)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        background_color: SLOT_COLOR.into(),
+        border_color: BORDER_COLOR.into(),
+        ..Default::default()
+    }
+}
+
+/// Spawn the hidden icon and the empty item count of a slot, both marked with `marker` so they
+/// can be found again to show a stack on them.
+pub fn spawn_item_contents(slot: &mut ChildBuilder, icons: &ItemIcons, marker: impl Component + Clone) {
+    let icon = AtlasImageBundle {
+        style: Style {
+            width: Val::Px(ICON_SIZE),
+            height: Val::Px(ICON_SIZE),
+            ..Default::default()
+        },
+        texture_atlas: icons.atlas.clone(),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    slot.spawn((icon, marker.clone()));
+    let count = TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: COUNT_FONT_SIZE,
+            color: Color::WHITE,
+            ..Default::default()
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        right: Val::Px(2.0),
+        bottom: Val::Px(0.0),
+        ..Default::default()
+    });
+    slot.spawn((count, marker));
+}
+
+/// spawn the empty hotbar
+fn spawn_hotbar(mut commands: Commands, icons: Res<ItemIcons>) {
+    let hotbar = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(SLOT_GAP * 2.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            column_gap: Val::Px(SLOT_GAP),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands.spawn(hotbar).with_children(|hotbar| {
+        for slot in 0..HOTBAR_SLOTS {
+            hotbar
+                .spawn((item_slot_node(), HotbarSlot(slot)))
+                .with_children(|node| spawn_item_contents(node, &icons, HotbarSlot(slot)));
+        }
+    });
+}
+
+/// show the items in the hotbar and highlight the selected slot
+fn update_hotbar(
+    inventory: Res<Inventory>,
+    registry: Res<BlockRegistry>,
+    item_icons: Res<ItemIcons>,
+    mut slots: Query<(&HotbarSlot, &mut BorderColor)>,
+    mut icons: Query<(&HotbarSlot, &mut UiTextureAtlasImage, &mut Visibility)>,
+    mut counts: Query<(&HotbarSlot, &mut Text)>,
+) {
+    for (slot, mut border) in slots.iter_mut() {
+        border.0 = if slot.0 == inventory.selected() {
+            SELECTED_BORDER_COLOR
+        } else {
+            BORDER_COLOR
+        };
+    }
+    let stacks = inventory.hotbar();
+    for (slot, mut icon, mut visibility) in icons.iter_mut() {
+        item_icons.show(stacks[slot.0], &registry, &mut icon, &mut visibility);
+    }
+    for (slot, mut text) in counts.iter_mut() {
+        text.sections[0].value = count_text(stacks[slot.0]);
+    }
+}
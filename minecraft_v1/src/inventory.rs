@@ -0,0 +1,269 @@
+use bevy::{input::mouse::MouseWheel, prelude::*};
+
+use crate::block_types::BlockType;
+
+/// the number of slots in the hotbar, which are the first slots of the inventory
+pub const HOTBAR_SLOTS: usize = 9;
+/// the number of slots in the player's inventory, including the hotbar
+pub const INVENTORY_SLOTS: usize = 36;
+
+/// the keys selecting each hotbar slot
+const HOTBAR_KEYS: [KeyCode; HOTBAR_SLOTS] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// Gives the player an [`Inventory`] and lets them pick the hotbar slot they are holding with
+/// the number keys and the scroll wheel.
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Inventory>()
+            .add_systems(Update, (select_hotbar_slot, scroll_hotbar));
+    }
+}
+
+/// A number of the same item in one inventory slot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ItemStack {
+    pub block: BlockType,
+    pub count: u16,
+}
+
+/// The items the player carries. The first [`HOTBAR_SLOTS`] slots make up the hotbar, one of
+/// which is selected and holds the block that is placed.
+#[derive(Resource, Clone, PartialEq, Debug)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+    /// the selected hotbar slot
+    selected: usize,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self::new(INVENTORY_SLOTS)
+    }
+}
+
+impl Inventory {
+    /// an empty inventory with `size` slots
+    ///
+    /// # Panics
+    /// if there are fewer slots than the hotbar has
+    pub fn new(size: usize) -> Self {
+        assert!(size >= HOTBAR_SLOTS, "an inventory needs at least {HOTBAR_SLOTS} slots");
+        Self {
+            slots: vec![None; size],
+            selected: 0,
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    pub fn slot_mut(&mut self, index: usize) -> &mut Option<ItemStack> {
+        &mut self.slots[index]
+    }
+
+    pub fn hotbar(&self) -> &[Option<ItemStack>] {
+        &self.slots[..HOTBAR_SLOTS]
+    }
+
+    /// the index of the selected hotbar slot
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// # Panics
+    /// if `slot` isn't a hotbar slot
+    pub fn select(&mut self, slot: usize) {
+        assert!(slot < HOTBAR_SLOTS, "slot {slot} isn't in the hotbar");
+        self.selected = slot;
+    }
+
+    /// move the selection `steps` slots to the right, wrapping around the ends of the hotbar
+    pub fn scroll(&mut self, steps: isize) {
+        self.selected = (self.selected as isize + steps).rem_euclid(HOTBAR_SLOTS as isize) as usize;
+    }
+
+    /// the stack in the selected hotbar slot
+    pub fn selected_stack(&self) -> Option<ItemStack> {
+        self.slots[self.selected]
+    }
+
+    /// the first hotbar slot holding `block`
+    pub fn hotbar_slot_of(&self, block: BlockType) -> Option<usize> {
+        self.hotbar().iter().position(|stack| stack.is_some_and(|stack| stack.block == block))
+    }
+
+    /// Put `count` of `block` into the inventory, topping up the stacks of `block` that aren't
+    /// full yet before starting new ones in the first empty slots. No stack grows beyond
+    /// `max_stack`. Returns how many didn't fit.
+    pub fn add(&mut self, block: BlockType, count: u16, max_stack: u16) -> u16 {
+        let mut remaining = count;
+        for stack in self.slots.iter_mut().flatten() {
+            if remaining == 0 {
+                return 0;
+            }
+            if stack.block == block && stack.count < max_stack {
+                let moved = remaining.min(max_stack - stack.count);
+                stack.count += moved;
+                remaining -= moved;
+            }
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if remaining == 0 {
+                return 0;
+            }
+            let moved = remaining.min(max_stack);
+            *slot = Some(ItemStack { block, count: moved });
+            remaining -= moved;
+        }
+        remaining
+    }
+
+    /// take one item out of the selected hotbar slot, emptying the slot if it was the last one
+    pub fn take_selected(&mut self) -> Option<BlockType> {
+        let slot = &mut self.slots[self.selected];
+        let stack = slot.as_mut()?;
+        let block = stack.block;
+        stack.count -= 1;
+        if stack.count == 0 {
+            *slot = None;
+        }
+        Some(block)
+    }
+}
+
+/// Click `slot` while `held` is carried by the mouse. An empty hand picks up the stack in the
+/// slot, a stack of the same item is put onto it as far as it fits below `max_stack`, and
+/// anything else swaps places with it.
+pub fn click_slot(slot: &mut Option<ItemStack>, held: &mut Option<ItemStack>, max_stack: u16) {
+    match (slot.as_mut(), held.as_mut()) {
+        (Some(stack), Some(carried)) if stack.block == carried.block => {
+            let moved = carried.count.min(max_stack.saturating_sub(stack.count));
+            stack.count += moved;
+            carried.count -= moved;
+            if carried.count == 0 {
+                *held = None;
+            }
+        }
+        _ => std::mem::swap(slot, held),
+    }
+}
+
+fn select_hotbar_slot(input: Res<Input<KeyCode>>, mut inventory: ResMut<Inventory>) {
+    if let Some(slot) = HOTBAR_KEYS.iter().position(|key| input.just_pressed(*key)) {
+        inventory.select(slot);
+    }
+}
+
+/// scrolling up moves the selection to the left, scrolling down to the right
+fn scroll_hotbar(mut wheel: EventReader<MouseWheel>, mut inventory: ResMut<Inventory>) {
+    let scrolled: f32 = wheel.read().map(|event| event.y).sum();
+    if scrolled != 0.0 {
+        inventory.scroll(-scrolled.signum() as isize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirt() -> BlockType {
+        BlockType::from_id(1)
+    }
+
+    fn stone() -> BlockType {
+        BlockType::from_id(3)
+    }
+
+    #[test]
+    fn tops_up_stacks_before_starting_new_ones() {
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.add(stone(), 10, 64), 0);
+        assert_eq!(inventory.add(dirt(), 1, 64), 0);
+        assert_eq!(inventory.add(stone(), 60, 64), 0);
+        assert_eq!(inventory.slots()[0], Some(ItemStack { block: stone(), count: 64 }));
+        assert_eq!(inventory.slots()[1], Some(ItemStack { block: dirt(), count: 1 }));
+        assert_eq!(inventory.slots()[2], Some(ItemStack { block: stone(), count: 6 }));
+    }
+
+    #[test]
+    fn respects_the_max_stack_size() {
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.add(stone(), 40, 16), 0);
+        let counts: Vec<u16> = inventory.slots().iter().flatten().map(|stack| stack.count).collect();
+        assert_eq!(counts, [16, 16, 8]);
+    }
+
+    #[test]
+    fn returns_what_does_not_fit() {
+        let mut inventory = Inventory::new(HOTBAR_SLOTS);
+        assert_eq!(inventory.add(stone(), 10 * 64, 64), 64);
+        assert!(inventory.slots().iter().all(|slot| slot.is_some_and(|stack| stack.count == 64)));
+        assert_eq!(inventory.add(dirt(), 1, 64), 1);
+    }
+
+    #[test]
+    fn takes_from_the_selected_slot() {
+        let mut inventory = Inventory::default();
+        inventory.add(stone(), 2, 64);
+        assert_eq!(inventory.take_selected(), Some(stone()));
+        assert_eq!(inventory.selected_stack(), Some(ItemStack { block: stone(), count: 1 }));
+        assert_eq!(inventory.take_selected(), Some(stone()));
+        assert_eq!(inventory.selected_stack(), None);
+        assert_eq!(inventory.take_selected(), None);
+
+        inventory.add(stone(), 1, 64);
+        inventory.select(1);
+        assert_eq!(inventory.take_selected(), None);
+    }
+
+    #[test]
+    fn scrolling_wraps_around_the_hotbar() {
+        let mut inventory = Inventory::default();
+        inventory.scroll(-1);
+        assert_eq!(inventory.selected(), HOTBAR_SLOTS - 1);
+        inventory.scroll(2);
+        assert_eq!(inventory.selected(), 1);
+    }
+
+    #[test]
+    fn clicking_slots_moves_stacks() {
+        let stack = |block, count| Some(ItemStack { block, count });
+        let (mut slot, mut held) = (stack(stone(), 5), None);
+        click_slot(&mut slot, &mut held, 64);
+        assert_eq!((slot, held), (None, stack(stone(), 5)));
+        click_slot(&mut slot, &mut held, 64);
+        assert_eq!((slot, held), (stack(stone(), 5), None));
+
+        let mut held = stack(stone(), 62);
+        click_slot(&mut slot, &mut held, 64);
+        assert_eq!((slot, held), (stack(stone(), 64), stack(stone(), 3)));
+
+        let mut held = stack(dirt(), 1);
+        click_slot(&mut slot, &mut held, 64);
+        assert_eq!((slot, held), (stack(dirt(), 1), stack(stone(), 64)));
+    }
+
+    #[test]
+    fn finds_blocks_in_the_hotbar() {
+        let mut inventory = Inventory::new(HOTBAR_SLOTS + 1);
+        inventory.add(dirt(), 64 * HOTBAR_SLOTS as u16, 64);
+        inventory.add(stone(), 1, 64);
+        assert_eq!(inventory.hotbar_slot_of(dirt()), Some(0));
+        // the stone ended up past the hotbar
+        assert_eq!(inventory.hotbar_slot_of(stone()), None);
+    }
+}
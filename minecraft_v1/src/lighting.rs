@@ -0,0 +1,420 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::chunk_manager::{send_remesh_events, Chunk, ChunkManager, Position, CHUNK_DEPTH, CHUNK_HEIGHT, CHUNK_WIDTH};
+use crate::chunk_mesher::Side;
+use crate::chunk_streaming::{receive_generated_chunks, ChunkLoaded};
+
+/// the brightest light level
+pub const MAX_LIGHT: u8 = 15;
+
+/// Keeps the sky and block light of the loaded chunks up to date. Chunks are lit on their own
+/// while they are generated, then light spreads across their borders once they are loaded, and
+/// edited blocks let light in or cast shadows as soon as they are placed or broken.
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, light_loaded_chunks.after(receive_generated_chunks))
+            .add_systems(PostUpdate, relight_edited_blocks.before(send_remesh_events));
+    }
+}
+
+/// The two kinds of light, which spread the same way but come from different places.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightKind {
+    /// light from the sky, which shines straight down through empty blocks without dimming
+    Sky,
+    /// light given off by glowing blocks
+    Block,
+}
+
+impl LightKind {
+    const ALL: [LightKind; 2] = [LightKind::Sky, LightKind::Block];
+}
+
+/// The light levels at one block, each from 0 to [`MAX_LIGHT`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Light {
+    pub sky: u8,
+    pub block: u8,
+}
+
+impl Light {
+    /// the light out in the open, used where the light isn't known
+    pub const DAYLIGHT: Light = Light {
+        sky: MAX_LIGHT,
+        block: 0,
+    };
+
+    pub fn get(self, kind: LightKind) -> u8 {
+        match kind {
+            LightKind::Sky => self.sky,
+            LightKind::Block => self.block,
+        }
+    }
+}
+
+/// Blocks and their light, addressed by position, that light can spread through.
+trait LightGrid {
+    fn block(&self, position: Position) -> Option<BlockType>;
+    fn light(&self, position: Position, kind: LightKind) -> Option<u8>;
+    fn set_light(&mut self, position: Position, kind: LightKind, level: u8);
+}
+
+/// a chunk on its own, addressed by local position
+impl LightGrid for Chunk {
+    fn block(&self, position: Position) -> Option<BlockType> {
+        self.get(position)
+    }
+
+    fn light(&self, position: Position, kind: LightKind) -> Option<u8> {
+        Chunk::light(self, position).map(|light| light.get(kind))
+    }
+
+    fn set_light(&mut self, position: Position, kind: LightKind, level: u8) {
+        Chunk::set_light(self, position, kind, level);
+    }
+}
+
+/// every loaded chunk, addressed by world position
+impl LightGrid for ChunkManager {
+    fn block(&self, position: Position) -> Option<BlockType> {
+        self.get_block(position)
+    }
+
+    fn light(&self, position: Position, kind: LightKind) -> Option<u8> {
+        self.get_light(position).map(|light| light.get(kind))
+    }
+
+    fn set_light(&mut self, position: Position, kind: LightKind, level: u8) {
+        ChunkManager::set_light(self, position, kind, level);
+    }
+}
+
+/// the light level `block` gets from a neighbour lit at `level`, on its `side`
+fn spread_level(registry: &BlockRegistry, kind: LightKind, level: u8, block: BlockType, side: Side) -> u8 {
+    // sky light falls through empty blocks without losing any of its strength
+    if kind == LightKind::Sky && side == Side::Bottom && level == MAX_LIGHT && !registry.is_visible(block) {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+/// the light `block` gives off itself
+fn emitted(registry: &BlockRegistry, kind: LightKind, block: BlockType) -> u8 {
+    match kind {
+        LightKind::Sky => 0,
+        LightKind::Block => registry.get(block).light,
+    }
+}
+
+/// Spread light outwards from every position in `queue`, breadth first, raising the light of
+/// every see-through block it reaches that is darker than the light arriving there.
+fn propagate(grid: &mut impl LightGrid, registry: &BlockRegistry, kind: LightKind, mut queue: VecDeque<Position>) {
+    while let Some(position) = queue.pop_front() {
+        let Some(level) = grid.light(position, kind).filter(|level| *level > 0) else {
+            continue;
+        };
+        for side in Side::ALL {
+            let neighbour = position + side.offset();
+            let Some(block) = grid.block(neighbour).filter(|block| registry.get(*block).transparent) else {
+                continue;
+            };
+            let spread = spread_level(registry, kind, level, block, side);
+            if grid.light(neighbour, kind).is_some_and(|current| current < spread) {
+                grid.set_light(neighbour, kind, spread);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+}
+
+/// Take away the light that spread out from each of `removed`, which held the given level of
+/// light and are dark now. Returns the lit positions bordering the darkened area, whose light
+/// has to be spread back into it with [`propagate`].
+fn remove(grid: &mut impl LightGrid, registry: &BlockRegistry, kind: LightKind, removed: Vec<(Position, u8)>) -> VecDeque<Position> {
+    let mut queue = VecDeque::from(removed);
+    let mut refill = VecDeque::new();
+    while let Some((position, level)) = queue.pop_front() {
+        for side in Side::ALL {
+            let neighbour = position + side.offset();
+            let Some(neighbour_level) = grid.light(neighbour, kind).filter(|level| *level > 0) else {
+                continue;
+            };
+            // the neighbour was lit from here if its light is weaker, or if it is a block the
+            // full sky light fell into from above
+            let lit_from_here = neighbour_level < level
+                || (kind == LightKind::Sky && side == Side::Bottom && level == MAX_LIGHT && neighbour_level == MAX_LIGHT);
+            if !lit_from_here {
+                refill.push_back(neighbour);
+                continue;
+            }
+            grid.set_light(neighbour, kind, 0);
+            queue.push_back((neighbour, neighbour_level));
+            // glowing blocks keep their own light
+            let glow = grid.block(neighbour).map_or(0, |block| emitted(registry, kind, block));
+            if glow > 0 {
+                grid.set_light(neighbour, kind, glow);
+                refill.push_back(neighbour);
+            }
+        }
+    }
+    refill
+}
+
+/// Light a chunk as if nothing was around it. Sky light falls down every column until it hits
+/// a block it can't pass, glowing blocks give off their light, and both spread out from there.
+pub fn light_chunk(chunk: &mut Chunk, registry: &BlockRegistry) {
+    let mut sky = VecDeque::new();
+    let mut glowing = VecDeque::new();
+    for x in 0..CHUNK_WIDTH as isize {
+        for z in 0..CHUNK_DEPTH as isize {
+            let mut level = MAX_LIGHT;
+            for y in (0..CHUNK_HEIGHT as isize).rev() {
+                let local = Position::new(x, y, z);
+                let block = chunk.get(local).expect("the column lies inside the chunk");
+                level = if registry.get(block).transparent {
+                    spread_level(registry, LightKind::Sky, level, block, Side::Bottom)
+                } else {
+                    0
+                };
+                if level > 0 {
+                    chunk.set_light(local, LightKind::Sky, level);
+                    sky.push_back(local);
+                }
+                let glow = emitted(registry, LightKind::Block, block);
+                if glow > 0 {
+                    chunk.set_light(local, LightKind::Block, glow);
+                    glowing.push_back(local);
+                }
+            }
+        }
+    }
+    propagate(chunk, registry, LightKind::Sky, sky);
+    propagate(chunk, registry, LightKind::Block, glowing);
+}
+
+/// Let the light of the newly loaded chunk at chunk coordinate `chunk` and the loaded chunks
+/// around it spread across the borders between them. The chunk has to be lit with
+/// [`light_chunk`] first.
+pub fn spread_across_borders(chunks: &mut ChunkManager, registry: &BlockRegistry, chunk: Position) {
+    let (width, depth) = (CHUNK_WIDTH as isize, CHUNK_DEPTH as isize);
+    let mut sources = LightKind::ALL.map(|_| VecDeque::new());
+    for (offset, length) in [
+        (Position::new(1, 0, 0), depth),
+        (Position::new(-1, 0, 0), depth),
+        (Position::new(0, 0, 1), width),
+        (Position::new(0, 0, -1), width),
+    ] {
+        let (Some(centre), Some(neighbour)) = (chunks.chunks.get(&chunk), chunks.chunks.get(&(chunk + offset))) else {
+            continue;
+        };
+        for i in 0..length {
+            // the local positions of the pair of blocks on either side of the border
+            let (inside, outside) = match (offset.x, offset.z) {
+                (1, _) => (Position::new(width - 1, 0, i), Position::new(0, 0, i)),
+                (-1, _) => (Position::new(0, 0, i), Position::new(width - 1, 0, i)),
+                (_, 1) => (Position::new(i, 0, depth - 1), Position::new(i, 0, 0)),
+                _ => (Position::new(i, 0, 0), Position::new(i, 0, depth - 1)),
+            };
+            for y in 0..CHUNK_HEIGHT as isize {
+                let (inside, outside) = (inside + Position::new(0, y, 0), outside + Position::new(0, y, 0));
+                let lit = |chunk: &Chunk, local: Position| {
+                    let block = chunk.get(local).expect("border blocks lie inside their chunk");
+                    (registry.get(block).transparent, chunk.light(local).expect("border blocks lie inside their chunk"))
+                };
+                let ((inside_clear, inside_light), (outside_clear, outside_light)) = (lit(centre, inside), lit(neighbour, outside));
+                for (kind, queue) in LightKind::ALL.into_iter().zip(&mut sources) {
+                    // only light that is brighter than what is already across can spread there
+                    let (inside_level, outside_level) = (inside_light.get(kind), outside_light.get(kind));
+                    if outside_clear && inside_level > outside_level + 1 {
+                        queue.push_back(ChunkManager::chunk_origin(chunk) + inside);
+                    } else if inside_clear && outside_level > inside_level + 1 {
+                        queue.push_back(ChunkManager::chunk_origin(chunk + offset) + outside);
+                    }
+                }
+            }
+        }
+    }
+    for (kind, queue) in LightKind::ALL.into_iter().zip(sources) {
+        propagate(chunks, registry, kind, queue);
+    }
+}
+
+/// Bring the light around a block that changed from `previous` to `block` up to date. Light
+/// that passed through or came from the old block is taken away, and the light around it
+/// spreads back in, including the new block's own glow.
+pub fn relight_block(chunks: &mut ChunkManager, registry: &BlockRegistry, position: Position, previous: BlockType, block: BlockType) {
+    let Some(old_light) = chunks.get_light(position) else {
+        return;
+    };
+    if previous == block {
+        return;
+    }
+    for kind in LightKind::ALL {
+        let old_level = old_light.get(kind);
+        chunks.set_light(position, kind, 0);
+        let mut refill = remove(chunks, registry, kind, vec![(position, old_level)]);
+        let glow = emitted(registry, kind, block);
+        if glow > 0 {
+            chunks.set_light(position, kind, glow);
+            refill.push_back(position);
+        }
+        // a block that lets light through is lit by its neighbours
+        if registry.get(block).transparent {
+            refill.extend(Side::ALL.map(|side| position + side.offset()));
+        }
+        propagate(chunks, registry, kind, refill);
+    }
+}
+
+/// spread the light of every chunk loaded this frame into the chunks around it
+pub fn light_loaded_chunks(mut loaded: EventReader<ChunkLoaded>, mut chunks: ResMut<ChunkManager>, registry: Res<BlockRegistry>) {
+    for ChunkLoaded(chunk) in loaded.read() {
+        spread_across_borders(&mut chunks, &registry, *chunk);
+    }
+}
+
+/// relight around every block edited this frame, in the order the edits were made
+fn relight_edited_blocks(mut chunks: ResMut<ChunkManager>, registry: Res<BlockRegistry>) {
+    let edits = chunks.edits().to_vec();
+    for edit in edits {
+        relight_block(&mut chunks, &registry, edit.position, edit.previous, edit.block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Blocks {
+        registry: BlockRegistry,
+        stone: BlockType,
+        lamp: BlockType,
+    }
+
+    fn blocks() -> Blocks {
+        let registry = BlockRegistry::from_assets();
+        Blocks {
+            stone: registry.by_name("stone").unwrap(),
+            lamp: registry.by_name("lamp").unwrap(),
+            registry,
+        }
+    }
+
+    /// Load chunks of air on a stone floor at y = 0 at each of `positions`, lighting them one by
+    /// one like the game does.
+    fn load(chunks: &mut ChunkManager, blocks: &Blocks, positions: &[Position]) {
+        for &position in positions {
+            let mut chunk = Chunk::new(ChunkManager::chunk_origin(position).into(), BlockType::AIR);
+            for x in 0..CHUNK_WIDTH as isize {
+                for z in 0..CHUNK_DEPTH as isize {
+                    chunk.set(Position::new(x, 0, z), blocks.stone);
+                }
+            }
+            light_chunk(&mut chunk, &blocks.registry);
+            chunks.chunks.insert(position, chunk);
+            spread_across_borders(chunks, &blocks.registry, position);
+        }
+    }
+
+    /// place `block` and relight around it, like happens at the end of every frame
+    fn place(chunks: &mut ChunkManager, blocks: &Blocks, position: Position, block: BlockType) {
+        let previous = chunks.set_block(position, block).unwrap();
+        relight_block(chunks, &blocks.registry, position, previous, block);
+    }
+
+    fn light(chunks: &ChunkManager, x: isize, y: isize, z: isize) -> Light {
+        chunks.get_light(Position::new(x, y, z)).unwrap()
+    }
+
+    #[test]
+    fn sky_light_fills_open_chunks() {
+        let blocks = blocks();
+        let mut chunks = ChunkManager::default();
+        load(&mut chunks, &blocks, &[Position::new(0, 0, 0)]);
+        assert_eq!(light(&chunks, 5, 1, 5), Light::DAYLIGHT);
+        assert_eq!(light(&chunks, 5, 200, 5), Light::DAYLIGHT);
+        // light doesn't get into solid blocks
+        assert_eq!(light(&chunks, 5, 0, 5), Light::default());
+    }
+
+    #[test]
+    fn block_light_spreads_across_chunk_borders() {
+        let blocks = blocks();
+        let mut chunks = ChunkManager::default();
+        load(&mut chunks, &blocks, &[Position::new(0, 0, 0)]);
+        place(&mut chunks, &blocks, Position::new(14, 1, 3), blocks.lamp);
+        assert_eq!(light(&chunks, 14, 1, 3).block, MAX_LIGHT);
+        assert_eq!(light(&chunks, 15, 1, 3).block, MAX_LIGHT - 1);
+
+        // the light reaches into a chunk loaded after the lamp was placed
+        load(&mut chunks, &blocks, &[Position::new(1, 0, 0)]);
+        assert_eq!(light(&chunks, 16, 1, 3).block, MAX_LIGHT - 2);
+        assert_eq!(light(&chunks, 20, 2, 5).block, MAX_LIGHT - 9);
+        assert_eq!(light(&chunks, 29, 1, 3).block, 0);
+
+        // and goes out again when the lamp is broken
+        place(&mut chunks, &blocks, Position::new(14, 1, 3), BlockType::AIR);
+        assert_eq!(light(&chunks, 16, 1, 3).block, 0);
+        assert_eq!(light(&chunks, 14, 1, 3).block, 0);
+    }
+
+    #[test]
+    fn light_spreads_from_chunks_that_were_loaded_first() {
+        let blocks = blocks();
+        let mut chunks = ChunkManager::default();
+        load(&mut chunks, &blocks, &[Position::new(-1, 0, 0)]);
+        place(&mut chunks, &blocks, Position::new(-1, 1, 0), blocks.lamp);
+        load(&mut chunks, &blocks, &[Position::new(0, 0, 0), Position::new(0, 0, -1)]);
+        assert_eq!(light(&chunks, 0, 1, 0).block, MAX_LIGHT - 1);
+        // around the corner, into the chunk diagonally across
+        assert_eq!(light(&chunks, 0, 1, -1).block, MAX_LIGHT - 2);
+    }
+
+    #[test]
+    fn a_roof_darkens_the_blocks_below_it() {
+        let blocks = blocks();
+        let mut chunks = ChunkManager::default();
+        let positions: Vec<Position> = (-1..=1).flat_map(|x| (-1..=1).map(move |z| Position::new(x, 0, z))).collect();
+        load(&mut chunks, &blocks, &positions);
+        assert_eq!(light(&chunks, 8, 1, 8).sky, MAX_LIGHT);
+
+        // a 9x9 roof at y = 5, centred on x = z = 8
+        for x in 4..=12 {
+            for z in 4..=12 {
+                place(&mut chunks, &blocks, Position::new(x, 5, z), blocks.stone);
+            }
+        }
+        // sky light only creeps in from the open sides, four blocks away
+        assert_eq!(light(&chunks, 8, 1, 8).sky, MAX_LIGHT - 5);
+        assert_eq!(light(&chunks, 4, 1, 8).sky, MAX_LIGHT - 1);
+        assert_eq!(light(&chunks, 3, 1, 8).sky, MAX_LIGHT);
+        assert_eq!(light(&chunks, 8, 6, 8).sky, MAX_LIGHT);
+
+        // breaking a hole in the roof lets the sky straight back in
+        place(&mut chunks, &blocks, Position::new(8, 5, 8), BlockType::AIR);
+        assert_eq!(light(&chunks, 8, 1, 8).sky, MAX_LIGHT);
+        assert_eq!(light(&chunks, 9, 1, 8).sky, MAX_LIGHT - 1);
+    }
+
+    #[test]
+    fn a_roof_over_a_chunk_border_darkens_both_chunks() {
+        let blocks = blocks();
+        let mut chunks = ChunkManager::default();
+        load(&mut chunks, &blocks, &[Position::new(0, 0, 0), Position::new(1, 0, 0)]);
+        for x in 10..=21 {
+            for z in 2..=13 {
+                place(&mut chunks, &blocks, Position::new(x, 3, z), blocks.stone);
+            }
+        }
+        assert_eq!(light(&chunks, 15, 1, 8).sky, MAX_LIGHT - 6);
+        assert_eq!(light(&chunks, 16, 1, 8).sky, MAX_LIGHT - 6);
+        assert_eq!(light(&chunks, 16, 4, 8).sky, MAX_LIGHT);
+    }
+}
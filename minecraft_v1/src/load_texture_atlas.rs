@@ -1,24 +1,251 @@
-use bevy::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
 
+use bevy::{
+    asset::io::file::FileAssetReader,
+    prelude::*,
+    render::texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+};
+use image::{imageops, DynamicImage, RgbaImage};
+
+/// the directory block textures are discovered in, relative to the assets directory
+pub const BLOCK_TEXTURES_DIRECTORY: &str = "textures/blocks";
+/// how many pixels around every tile repeat the tile's edge, so the neighbouring tiles don't
+/// bleed into it when the atlas is sampled at a lower resolution
+const PADDING: u32 = 4;
+
+/// Every block texture, packed into a single image. Textures are looked up by their file name
+/// without the extension, so `textures/blocks/grass_top.png` is called `grass_top`.
 #[derive(Resource)]
 pub struct TextureAtlas {
     pub handle: Option<Handle<Image>>,
+    /// the packed image, until it is handed to the image assets at startup
+    image: Option<Image>,
+    pub layout: AtlasLayout,
+    tiles: HashMap<String, (u32, u32)>,
+}
+
+/// How the texture atlas is divided into equally sized tiles. Tiles are addressed by
+/// (column, row), counting from the top left corner of the atlas.
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasLayout {
+    pub columns: u32,
+    pub rows: u32,
+    /// the part of a tile's width and height taken up by the padding on each of its edges
+    pub padding: f32,
+}
+
+impl AtlasLayout {
+    /// the part of the atlas covered by `tile` without its padding, in UV coordinates
+    pub fn tile_rect(&self, (column, row): (u32, u32)) -> Rect {
+        let size = Vec2::new(1.0 / self.columns as f32, 1.0 / self.rows as f32);
+        let min = Vec2::new(column as f32, row as f32) * size;
+        Rect::from_corners(min + size * self.padding, min + size * (1.0 - self.padding))
+    }
+}
+
+/// Every block texture, stacked into the layers of a single 2D array texture in the same order
+/// as the tiles of the [`TextureAtlas`]. Unlike the atlas, a layer can be repeated across a
+/// quad, and its edges never bleed into other textures.
+#[derive(Resource)]
+pub struct TextureArray {
+    pub handle: Option<Handle<Image>>,
+    /// the stacked image, until it is handed to the image assets at startup
+    image: Option<Image>,
+    layers: HashMap<String, u32>,
 }
 
 pub struct LoadTextureAtlasPlugin;
 
 impl Plugin for LoadTextureAtlasPlugin {
     fn build(&self, app: &mut App) {
+        let directory = FileAssetReader::get_base_path()
+            .join("assets")
+            .join(BLOCK_TEXTURES_DIRECTORY);
+        let (atlas, array) = match read_block_textures(&directory) {
+            Ok(textures) => textures,
+            Err(error) => panic!("failed to read the block textures from {}: {error}", directory.display()),
+        };
         app
-            .insert_resource(TextureAtlas { handle: None })
+            .insert_resource(atlas)
+            .insert_resource(array)
             .add_systems(Startup, load_texture_atlas);
     }
 }
 
+/// read every block texture in `directory`, and pack them into both an atlas and an array
+pub fn read_block_textures(directory: &Path) -> io::Result<(TextureAtlas, TextureArray)> {
+    let textures = BlockTextureImages::read(directory)?;
+    Ok((TextureAtlas::pack(&textures), TextureArray::stack(&textures)))
+}
+
+/// The block textures read from disk, sorted by name and scaled to the same size.
+struct BlockTextureImages {
+    tile_size: u32,
+    textures: Vec<(String, RgbaImage)>,
+}
+
+impl BlockTextureImages {
+    /// read every PNG in `directory`, scaling them to the size of the largest one
+    fn read(directory: &Path) -> io::Result<Self> {
+        let mut textures = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "png") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let texture = image::open(&path)
+                .map_err(|error| invalid_data(format!("{}: {error}", path.display())))?
+                .into_rgba8();
+            textures.push((name.to_owned(), texture));
+        }
+        if textures.is_empty() {
+            return Err(invalid_data("there are no block textures"));
+        }
+        // the files are listed in no particular order, sorting keeps the atlas the same
+        textures.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let tile_size = textures
+            .iter()
+            .map(|(_, texture)| texture.width().max(texture.height()))
+            .max()
+            .expect("there is at least one texture");
+        for (_, texture) in &mut textures {
+            if texture.dimensions() != (tile_size, tile_size) {
+                *texture = imageops::resize(texture, tile_size, tile_size, imageops::FilterType::Nearest);
+            }
+        }
+        Ok(Self { tile_size, textures })
+    }
+}
+
+impl TextureAtlas {
+    /// pack the textures into one image, as a grid that is about as wide as it is high
+    fn pack(images: &BlockTextureImages) -> Self {
+        let BlockTextureImages { tile_size, textures } = images;
+        let tile_size = *tile_size;
+        let cell_size = tile_size + 2 * PADDING;
+        let columns = (textures.len() as f32).sqrt().ceil() as u32;
+        let rows = (textures.len() as u32).div_ceil(columns);
+
+        let mut atlas = RgbaImage::new(columns * cell_size, rows * cell_size);
+        let mut tiles = HashMap::new();
+        for (i, (name, texture)) in textures.iter().enumerate() {
+            let tile = (i as u32 % columns, i as u32 / columns);
+            for y in 0..cell_size {
+                for x in 0..cell_size {
+                    // the padding repeats the closest pixel on the texture's edge
+                    let source_x = x.saturating_sub(PADDING).min(tile_size - 1);
+                    let source_y = y.saturating_sub(PADDING).min(tile_size - 1);
+                    let pixel = *texture.get_pixel(source_x, source_y);
+                    atlas.put_pixel(tile.0 * cell_size + x, tile.1 * cell_size + y, pixel);
+                }
+            }
+            tiles.insert(name.clone(), tile);
+        }
+
+        Self {
+            handle: None,
+            image: Some(Image::from_dynamic(DynamicImage::ImageRgba8(atlas), true)),
+            layout: AtlasLayout {
+                columns,
+                rows,
+                padding: PADDING as f32 / cell_size as f32,
+            },
+            tiles,
+        }
+    }
+
+    /// the tile of the texture called `name`
+    pub fn tile(&self, name: &str) -> Option<(u32, u32)> {
+        self.tiles.get(name).copied()
+    }
+}
+
+impl TextureArray {
+    /// stack the textures on top of each other, one layer each
+    fn stack(images: &BlockTextureImages) -> Self {
+        let BlockTextureImages { tile_size, textures } = images;
+        let mut stacked = RgbaImage::new(*tile_size, tile_size * textures.len() as u32);
+        let mut layers = HashMap::new();
+        for (layer, (name, texture)) in textures.iter().enumerate() {
+            imageops::replace(&mut stacked, texture, 0, (layer as u32 * tile_size) as i64);
+            layers.insert(name.clone(), layer as u32);
+        }
+
+        let mut image = Image::from_dynamic(DynamicImage::ImageRgba8(stacked), true);
+        image.reinterpret_stacked_2d_as_array(textures.len() as u32);
+        // layers are repeated once per block across the faces of greedy quads
+        image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: ImageAddressMode::Repeat,
+            address_mode_v: ImageAddressMode::Repeat,
+            ..ImageSamplerDescriptor::nearest()
+        });
+        Self {
+            handle: None,
+            image: Some(image),
+            layers,
+        }
+    }
+
+    /// the layer of the texture called `name`
+    pub fn layer(&self, name: &str) -> Option<u32> {
+        self.layers.get(name).copied()
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// hand the packed atlas and the texture array to the image assets, so materials can use them
 pub fn load_texture_atlas(
-    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
     mut texture_atlas: ResMut<TextureAtlas>,
+    mut texture_array: ResMut<TextureArray>,
 ) {
-    let texture_handle = asset_server.load("textures/blocks/texture_atlas.png");
-    texture_atlas.handle = Some(texture_handle);
-}
\ No newline at end of file
+    if let Some(image) = texture_atlas.image.take() {
+        texture_atlas.handle = Some(images.add(image));
+    }
+    if let Some(image) = texture_array.image.take() {
+        texture_array.handle = Some(images.add(image));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_rects_cover_their_tile_without_its_padding() {
+        let layout = AtlasLayout { columns: 4, rows: 2, padding: 0.125 };
+        assert_eq!(layout.tile_rect((0, 0)), Rect::new(0.03125, 0.0625, 0.21875, 0.4375));
+        assert_eq!(layout.tile_rect((1, 1)), Rect::new(0.28125, 0.5625, 0.46875, 0.9375));
+        assert_eq!(layout.tile_rect((3, 1)), Rect::new(0.78125, 0.5625, 0.96875, 0.9375));
+    }
+
+    #[test]
+    fn tile_rects_match_the_packed_textures() {
+        // five textures of a single colour each, in a 3 by 2 grid
+        let textures = (0..5u8)
+            .map(|i| (format!("texture_{i}"), RgbaImage::from_pixel(16, 16, image::Rgba([i, 0, 0, 255]))))
+            .collect();
+        let atlas = TextureAtlas::pack(&BlockTextureImages { tile_size: 16, textures });
+        let image = atlas.image.as_ref().unwrap();
+        let size = Vec2::new(image.width() as f32, image.height() as f32);
+        assert_eq!(size, Vec2::new(3.0, 2.0) * (16 + 2 * PADDING) as f32);
+
+        let tile = atlas.tile("texture_4").unwrap();
+        assert_eq!(tile, (1, 1));
+        // in pixels, up to rounding
+        let rect = atlas.layout.tile_rect(tile);
+        let cell = (16 + 2 * PADDING) as f32;
+        assert_eq!((rect.min * size).round(), Vec2::splat(cell + PADDING as f32));
+        assert_eq!((rect.max * size).round(), Vec2::splat(cell + PADDING as f32 + 16.0));
+    }
+}
@@ -3,54 +3,94 @@ mod player_movement;
 mod load_texture_atlas;
 mod chunk_manager;
 mod block_types;
+mod chunk_storage;
+mod chunk_mesher;
+mod terrain_generator;
+mod chunk_streaming;
+mod world_storage;
+mod array_texture_material;
+mod block_targeting;
+mod block_editing;
+mod inventory;
+mod hotbar_hud;
+mod crafting;
+mod crafting_screen;
+mod lighting;
+mod day_night;
+mod fluids;
+mod falling_blocks;
+mod decoration;
 
 use bevy::{prelude::*, pbr::wireframe::{WireframePlugin, WireframeConfig}};
 use bevy_flycam::prelude::*;
 use block_spawner::BlockSpawnerPlugin;
-use player_movement::PlayerMovementPlugin;
+use player_movement::{Player, PlayerMovementPlugin};
 use load_texture_atlas::LoadTextureAtlasPlugin;
 use chunk_manager::ChunkManagerPlugin;
+use terrain_generator::{TerrainGenerator, TerrainGeneratorPlugin};
+use chunk_streaming::ChunkStreamingPlugin;
+use world_storage::WorldStoragePlugin;
+use array_texture_material::ArrayTextureMaterialPlugin;
+use block_targeting::BlockTargetingPlugin;
+use block_editing::BlockEditingPlugin;
+use inventory::InventoryPlugin;
+use hotbar_hud::HotbarHudPlugin;
+use crafting::CraftingPlugin;
+use crafting_screen::CraftingScreenPlugin;
+use lighting::LightingPlugin;
+use day_night::DayNightPlugin;
+use fluids::FluidsPlugin;
+use falling_blocks::FallingBlocksPlugin;
+use decoration::DecorationPlugin;
 
 fn main() {
     App::new()
-        .insert_resource(ClearColor(Color::rgb(0.0, 0.7, 1.0)))
-        .insert_resource(AmbientLight {
-            color: Color::rgb(0.8, 0.8, 0.8),
-            brightness: 1.0,
-        })
         .add_plugins((
             DefaultPlugins.set(ImagePlugin::default_nearest()),
-            BlockSpawnerPlugin,
-            PlayerMovementPlugin,
             LoadTextureAtlasPlugin,
+            ArrayTextureMaterialPlugin,
+            BlockSpawnerPlugin,
             ChunkManagerPlugin,
+            TerrainGeneratorPlugin,
+            WorldStoragePlugin,
+            ChunkStreamingPlugin,
+            LightingPlugin,
             WireframePlugin,
-            PlayerPlugin,
+            NoCameraPlayerPlugin,
+        ))
+        .add_plugins((
+            PlayerMovementPlugin,
+            BlockTargetingPlugin,
+            BlockEditingPlugin,
+            InventoryPlugin,
+            HotbarHudPlugin,
+            CraftingPlugin,
+            CraftingScreenPlugin,
+            DayNightPlugin,
+            FluidsPlugin,
+            FallingBlocksPlugin,
+            DecorationPlugin,
         ))
         .add_systems(Startup, (
-            spawn_sun, 
+            spawn_camera,
             use_wireframe
         ))
         .run();
 
 }
 
-fn spawn_sun(mut commands: Commands) {
-    let light = DirectionalLightBundle {
-        transform: Transform::from_xyz(0.0, 5.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..Default::default()
-    };
-
-    commands.spawn(light);
-}
-
 fn use_wireframe(mut wireframe_config: ResMut<WireframeConfig>) {
     wireframe_config.global = true;
 }
 
-fn _spawn_camera(mut commands: Commands) {
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(0.0, 50.0, 50.0),
-        ..Default::default()
-    });
+fn spawn_camera(mut commands: Commands, generator: Res<TerrainGenerator>) {
+    // start just above the terrain, chunks are loaded around wherever the camera is
+    let height = generator.height_at(0, 0) + 2;
+    commands.spawn((
+        Camera3dBundle {
+            transform: Transform::from_xyz(0.0, height as f32, 0.0),
+            ..Default::default()
+        },
+        Player::default(),
+    ));
 }
\ No newline at end of file
@@ -1,38 +1,430 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::system::SystemParam,
+    input::mouse::MouseMotion,
+    prelude::*,
+    window::{CursorGrabMode, PrimaryWindow},
+};
+use bevy_flycam::prelude::{KeyBindings, MovementSettings};
 
-const _PLAYER_SPEED: f32 = 15.0;
+use crate::block_types::BlockRegistry;
+use crate::chunk_manager::{ChunkManager, Position, CHUNK_HEIGHT};
 
+/// width of the player's bounding box along x and z
+const PLAYER_WIDTH: f32 = 0.6;
+/// height of the player's bounding box
+const PLAYER_HEIGHT: f32 = 1.8;
+/// height of the camera above the bottom of the bounding box
+const EYE_HEIGHT: f32 = 1.62;
+/// walking speed in blocks per second
+const WALK_SPEED: f32 = 4.3;
+/// walking speed while sprinting
+const SPRINT_SPEED: f32 = 5.6;
+/// downwards acceleration in blocks per second squared
+const GRAVITY: f32 = 32.0;
+/// the fastest the player can fall
+const TERMINAL_VELOCITY: f32 = 78.0;
+/// upwards speed at the start of a jump, enough to jump on top of a block
+const JUMP_SPEED: f32 = 9.0;
+/// obstacles up to this high are walked onto without jumping
+const STEP_HEIGHT: f32 = 0.6;
+/// how much faster the player flies while sprinting
+const FLY_SPRINT_FACTOR: f32 = 3.0;
+/// frames taking longer than this are simulated as if they took this long, so a hitch doesn't
+/// throw the player across the world
+const MAX_STEP: f32 = 0.05;
+/// keeps the bounding box from counting as inside a block it only touches
+const EPSILON: f32 = 1e-4;
+
+const TOGGLE_FLYING: KeyCode = KeyCode::F;
+const SPRINT: KeyCode = KeyCode::ControlLeft;
+
+/// Lets the [`Player`] look around with the mouse and walk or fly around the world. Uses the
+/// [`MovementSettings`] and [`KeyBindings`] of `bevy_flycam`, whose `NoCameraPlayerPlugin` grabs
+/// the cursor.
 pub struct PlayerMovementPlugin;
 
 impl Plugin for PlayerMovementPlugin {
-    fn build(&self, _app: &mut App) {
-        
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<MovementSettings>()
+            .init_resource::<KeyBindings>()
+            .add_systems(Update, (look_around, toggle_flying, move_player).chain());
+    }
+}
+
+/// The player, on the camera it looks through. The camera sits [`EYE_HEIGHT`] above the bottom
+/// of the player's bounding box.
+#[derive(Component, Default)]
+pub struct Player {
+    /// in blocks per second
+    pub velocity: Vec3,
+    /// whether the player is standing on a solid block
+    pub on_ground: bool,
+    /// flying players ignore gravity and pass through blocks
+    pub flying: bool,
+}
+
+impl Player {
+    /// the bounding box of a player whose camera is at `eye`
+    pub fn bounding_box(eye: Vec3) -> Aabb {
+        let feet = eye - Vec3::Y * EYE_HEIGHT;
+        let half_width = PLAYER_WIDTH / 2.0;
+        Aabb {
+            min: feet - Vec3::new(half_width, 0.0, half_width),
+            max: feet + Vec3::new(half_width, PLAYER_HEIGHT, half_width),
+        }
+    }
+}
+
+/// The blocks entities collide with. Blocks in chunks that aren't loaded yet count as solid, so
+/// nothing falls through the world while it is loading, and everything above the world is empty.
+#[derive(SystemParam)]
+pub struct SolidBlocks<'w> {
+    chunks: Res<'w, ChunkManager>,
+    registry: Res<'w, BlockRegistry>,
+}
+
+impl SolidBlocks<'_> {
+    /// whether the block at world position `block` stops entities from moving through it
+    pub fn is_solid(&self, block: Position) -> bool {
+        if block.y >= CHUNK_HEIGHT as isize {
+            return false;
+        }
+        self.chunks.get_block(block).is_none_or(|block| self.registry.get(block).solid)
+    }
+}
+
+/// An axis-aligned bounding box in world space.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn translated(self, offset: Vec3) -> Self {
+        Self {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    /// whether the box overlaps the block at `block`, not counting touching faces
+    pub fn intersects_block(&self, block: Position) -> bool {
+        let block_min = Vec3::from(block);
+        let block_max = block_min + Vec3::ONE;
+        (self.min + EPSILON).cmplt(block_max).all() && (self.max - EPSILON).cmpgt(block_min).all()
+    }
+
+    /// the blocks the box overlaps along `axis`, not counting touching faces
+    fn block_range(&self, axis: usize) -> std::ops::Range<isize> {
+        (self.min[axis] + EPSILON).floor() as isize..(self.max[axis] - EPSILON).ceil() as isize
+    }
+}
+
+/// The result of [`move_and_collide`].
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Movement {
+    /// how far the box actually moved
+    pub offset: Vec3,
+    /// the axes along which a block stopped the box
+    pub blocked: BVec3,
+}
+
+/// How far `aabb` can move `distance` along `axis` (0 = x, 1 = y, 2 = z) before it runs into a
+/// block for which `is_solid` is true. Only blocks the box would move into are checked, so a box
+/// that already overlaps a block can still move out of it.
+pub fn sweep_axis(aabb: &Aabb, axis: usize, distance: f32, is_solid: &impl Fn(Position) -> bool) -> f32 {
+    if distance == 0.0 {
+        return 0.0;
+    }
+    let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+    let layer_is_solid = |layer: isize| {
+        aabb.block_range(u_axis).any(|u| {
+            aabb.block_range(v_axis).any(|v| {
+                let mut block = [0; 3];
+                block[axis] = layer;
+                block[u_axis] = u;
+                block[v_axis] = v;
+                is_solid(Position::new(block[0], block[1], block[2]))
+            })
+        })
+    };
+
+    if distance > 0.0 {
+        let first = (aabb.max[axis] - EPSILON).ceil() as isize;
+        let last = (aabb.max[axis] + distance - EPSILON).ceil() as isize - 1;
+        for layer in first..=last {
+            if layer_is_solid(layer) {
+                return (layer as f32 - aabb.max[axis]).max(0.0);
+            }
+        }
+    } else {
+        let first = (aabb.min[axis] + EPSILON).floor() as isize - 1;
+        let last = (aabb.min[axis] + distance + EPSILON).floor() as isize;
+        for layer in (last..=first).rev() {
+            if layer_is_solid(layer) {
+                return ((layer + 1) as f32 - aabb.min[axis]).min(0.0);
+            }
+        }
+    }
+    distance
+}
+
+/// Move `aabb` by `motion`, one axis at a time starting with y, stopping at solid blocks and
+/// sliding along them on the other axes. A box that is `on_ground` and runs into an obstacle no
+/// higher than `step_height` is lifted on top of it.
+pub fn move_and_collide(
+    aabb: Aabb,
+    motion: Vec3,
+    on_ground: bool,
+    step_height: f32,
+    is_solid: &impl Fn(Position) -> bool,
+) -> Movement {
+    let movement = slide(aabb, motion, is_solid);
+    let blocked_sideways = movement.blocked.x || movement.blocked.z;
+    if !on_ground || !blocked_sideways || step_height <= 0.0 {
+        return movement;
+    }
+
+    // try again from higher up, and settle back down on whatever is underneath
+    let lift = sweep_axis(&aabb, 1, step_height, is_solid);
+    let lifted = aabb.translated(Vec3::Y * lift);
+    let stepped = slide(lifted, Vec3::new(motion.x, 0.0, motion.z), is_solid);
+    let raised = lifted.translated(stepped.offset);
+    let drop = sweep_axis(&raised, 1, -lift, is_solid);
+    let offset = stepped.offset + Vec3::Y * (lift + drop);
+    let horizontal = |offset: Vec3| offset.x * offset.x + offset.z * offset.z;
+    if horizontal(offset) <= horizontal(movement.offset) + EPSILON {
+        return movement;
+    }
+    Movement {
+        offset,
+        blocked: BVec3::new(stepped.blocked.x, false, stepped.blocked.z),
+    }
+}
+
+/// move `aabb` by `motion` one axis at a time, y first
+fn slide(aabb: Aabb, motion: Vec3, is_solid: &impl Fn(Position) -> bool) -> Movement {
+    let mut moved = aabb;
+    let mut offset = Vec3::ZERO;
+    let mut blocked = [false; 3];
+    for axis in [1, 0, 2] {
+        let distance = sweep_axis(&moved, axis, motion[axis], is_solid);
+        blocked[axis] = distance != motion[axis];
+        offset[axis] = distance;
+        let mut step = Vec3::ZERO;
+        step[axis] = distance;
+        moved = moved.translated(step);
+    }
+    Movement {
+        offset,
+        blocked: BVec3::new(blocked[0], blocked[1], blocked[2]),
     }
 }
 
-fn _move_player(
+/// turn and tilt the player's camera with the mouse while the cursor is grabbed
+fn look_around(
+    settings: Res<MovementSettings>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut motion: EventReader<MouseMotion>,
+    mut query: Query<&mut Transform, With<Player>>,
+) {
+    let Ok(window) = window.get_single() else {
+        motion.clear();
+        return;
+    };
+    if window.cursor.grab_mode == CursorGrabMode::None {
+        motion.clear();
+        return;
+    }
+    // the smaller of the window's sides keeps horizontal and vertical sensitivity the same
+    let window_scale = window.height().min(window.width());
+    let delta: Vec2 = motion.read().map(|event| event.delta).sum();
+    for mut transform in query.iter_mut() {
+        let (mut yaw, mut pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+        pitch -= (settings.sensitivity * delta.y * window_scale).to_radians();
+        yaw -= (settings.sensitivity * delta.x * window_scale).to_radians();
+        pitch = pitch.clamp(-1.54, 1.54);
+        transform.rotation = Quat::from_axis_angle(Vec3::Y, yaw) * Quat::from_axis_angle(Vec3::X, pitch);
+    }
+}
+
+/// switch between walking and flying when F is pressed
+fn toggle_flying(input: Res<Input<KeyCode>>, mut query: Query<&mut Player>) {
+    if !input.just_pressed(TOGGLE_FLYING) {
+        return;
+    }
+    for mut player in query.iter_mut() {
+        player.flying = !player.flying;
+        player.velocity = Vec3::ZERO;
+        player.on_ground = false;
+    }
+}
+
+/// Move the player by the keys held down. Walking players fall, jump and collide with solid
+/// blocks, flying players go wherever they look.
+pub fn move_player(
     time: Res<Time>,
     input: Res<Input<KeyCode>>,
-    mut query: Query<&mut Transform, With<Camera>>
+    settings: Res<MovementSettings>,
+    key_bindings: Res<KeyBindings>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    blocks: SolidBlocks,
+    mut query: Query<(&mut Player, &mut Transform)>,
 ) {
-    for mut transform in query.iter_mut() {
-        let mut translation = transform.translation;
-        if input.pressed(KeyCode::W) {
-            let forward = transform.forward();
-            translation += forward * time.delta_seconds() * _PLAYER_SPEED;
+    let delta = time.delta_seconds().min(MAX_STEP);
+    let grabbed = window
+        .get_single()
+        .is_ok_and(|window| window.cursor.grab_mode != CursorGrabMode::None);
+    let pressed = |key: KeyCode| grabbed && input.pressed(key);
+    let is_solid = |block: Position| blocks.is_solid(block);
+
+    for (mut player, mut transform) in query.iter_mut() {
+        let local_z = transform.local_z();
+        let forward = -Vec3::new(local_z.x, 0.0, local_z.z).normalize_or_zero();
+        let right = Vec3::new(-forward.z, 0.0, forward.x);
+        let mut wish = Vec3::ZERO;
+        if pressed(key_bindings.move_forward) {
+            wish += forward;
+        }
+        if pressed(key_bindings.move_backward) {
+            wish -= forward;
+        }
+        if pressed(key_bindings.move_right) {
+            wish += right;
         }
-        if input.pressed(KeyCode::S) {
-            let forward = transform.forward();
-            translation -= forward * time.delta_seconds() * _PLAYER_SPEED;
+        if pressed(key_bindings.move_left) {
+            wish -= right;
         }
-        if input.pressed(KeyCode::A) {
-            let left = transform.left();
-            translation += left * time.delta_seconds() * _PLAYER_SPEED;
+        let wish = wish.normalize_or_zero();
+        let sprinting = pressed(SPRINT);
+
+        if player.flying {
+            let mut velocity = wish;
+            if pressed(key_bindings.move_ascend) {
+                velocity += Vec3::Y;
+            }
+            if pressed(key_bindings.move_descend) {
+                velocity -= Vec3::Y;
+            }
+            let speed = settings.speed * if sprinting { FLY_SPRINT_FACTOR } else { 1.0 };
+            transform.translation += velocity.normalize_or_zero() * speed * delta;
+            continue;
         }
-        if input.pressed(KeyCode::D) {
-            let right = transform.right();
-            translation += right * time.delta_seconds() * _PLAYER_SPEED;
+
+        let speed = if sprinting { SPRINT_SPEED } else { WALK_SPEED };
+        player.velocity.x = wish.x * speed;
+        player.velocity.z = wish.z * speed;
+        if player.on_ground && pressed(key_bindings.move_ascend) {
+            player.velocity.y = JUMP_SPEED;
         }
-        transform.translation = translation;
+        player.velocity.y = (player.velocity.y - GRAVITY * delta).max(-TERMINAL_VELOCITY);
+
+        let aabb = Player::bounding_box(transform.translation);
+        let movement = move_and_collide(aabb, player.velocity * delta, player.on_ground, STEP_HEIGHT, &is_solid);
+        transform.translation += movement.offset;
+        player.on_ground = movement.blocked.y && player.velocity.y < 0.0;
+        if movement.blocked.y {
+            player.velocity.y = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a box the size of the player standing on the block at `feet`
+    fn player_at(feet: Vec3) -> Aabb {
+        Player::bounding_box(feet + Vec3::Y * EYE_HEIGHT)
     }
-}
\ No newline at end of file
+
+    /// a flat floor at y = 0, so the player stands at y = 1
+    fn floor(block: Position) -> bool {
+        block.y <= 0
+    }
+
+    #[test]
+    fn lands_on_the_floor() {
+        let aabb = player_at(Vec3::new(0.5, 3.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(0.0, -5.0, 0.0), false, STEP_HEIGHT, &floor);
+        assert!(movement.blocked.y);
+        assert!((movement.offset.y + 2.0).abs() < 1e-3, "{movement:?}");
+    }
+
+    #[test]
+    fn fast_falls_do_not_pass_through_the_floor() {
+        let aabb = player_at(Vec3::new(0.5, 100.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(0.0, -500.0, 0.0), false, STEP_HEIGHT, &floor);
+        assert!((aabb.min.y + movement.offset.y - 1.0).abs() < 1e-3, "{movement:?}");
+    }
+
+    #[test]
+    fn stops_at_the_ceiling() {
+        let ceiling = |block: Position| floor(block) || block.y == 4;
+        let aabb = player_at(Vec3::new(0.5, 1.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(0.0, 2.0, 0.0), true, STEP_HEIGHT, &ceiling);
+        assert!(movement.blocked.y);
+        assert!((aabb.max.y + movement.offset.y - 4.0).abs() < 1e-3, "{movement:?}");
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        // a wall filling x >= 2
+        let wall = |block: Position| floor(block) || block.x >= 2;
+        let aabb = player_at(Vec3::new(1.5, 1.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(1.0, 0.0, 1.0), true, STEP_HEIGHT, &wall);
+        assert!(movement.blocked.x && !movement.blocked.z);
+        assert!((aabb.max.x + movement.offset.x - 2.0).abs() < 1e-3, "{movement:?}");
+        assert_eq!(movement.offset.z, 1.0);
+        assert_eq!(movement.offset.y, 0.0);
+    }
+
+    #[test]
+    fn moves_out_of_blocks_it_is_stuck_in() {
+        let stuck = |block: Position| block == Position::new(0, 1, 0);
+        let aabb = player_at(Vec3::new(0.5, 1.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(1.0, 0.0, 0.0), false, STEP_HEIGHT, &stuck);
+        assert_eq!(movement.offset.x, 1.0);
+    }
+
+    #[test]
+    fn steps_onto_low_ledges() {
+        // a ledge one block high from x = 2 onwards
+        let ledge = |block: Position| floor(block) || (block.x >= 2 && block.y == 1);
+        let aabb = player_at(Vec3::new(1.5, 1.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(0.5, 0.0, 0.0), true, 1.0, &ledge);
+        assert!((movement.offset.y - 1.0).abs() < 1e-3, "{movement:?}");
+        assert!((movement.offset.x - 0.5).abs() < 1e-3, "{movement:?}");
+        assert!(!movement.blocked.x);
+    }
+
+    #[test]
+    fn does_not_step_onto_high_walls() {
+        let wall = |block: Position| floor(block) || (block.x >= 2 && block.y <= 2);
+        let aabb = player_at(Vec3::new(1.5, 1.0, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(0.5, 0.0, 0.0), true, 1.0, &wall);
+        assert_eq!(movement.offset.y, 0.0);
+        assert!(movement.blocked.x);
+    }
+
+    #[test]
+    fn does_not_step_up_in_the_air() {
+        let ledge = |block: Position| floor(block) || (block.x >= 2 && block.y == 1);
+        let aabb = player_at(Vec3::new(1.5, 1.5, 0.5));
+        let movement = move_and_collide(aabb, Vec3::new(0.5, 0.0, 0.0), false, 1.0, &ledge);
+        assert!(movement.blocked.x);
+        assert_eq!(movement.offset.y, 0.0);
+    }
+
+    #[test]
+    fn intersects_only_overlapping_blocks() {
+        let aabb = player_at(Vec3::new(0.5, 1.0, 0.5));
+        assert!(aabb.intersects_block(Position::new(0, 1, 0)));
+        assert!(aabb.intersects_block(Position::new(0, 2, 0)));
+        assert!(!aabb.intersects_block(Position::new(0, 0, 0)));
+        assert!(!aabb.intersects_block(Position::new(0, 3, 0)));
+        assert!(!aabb.intersects_block(Position::new(1, 1, 0)));
+    }
+}
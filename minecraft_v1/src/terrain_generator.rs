@@ -0,0 +1,257 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::chunk_manager::{Chunk, ChunkManager, Position, CHUNK_DEPTH, CHUNK_HEIGHT, CHUNK_WIDTH};
+use crate::decoration::decorate;
+
+/// Water fills every air block below this height.
+pub const SEA_LEVEL: usize = 64;
+/// the height the terrain is centred around
+const BASE_HEIGHT: f64 = 68.0;
+/// how far the terrain reaches above and below [`BASE_HEIGHT`]
+const HEIGHT_VARIATION: f64 = 28.0;
+/// the number of noise layers summed up to build the heightmap
+const OCTAVES: usize = 5;
+/// the size in blocks of the largest hills
+const BASE_WAVELENGTH: f64 = 192.0;
+/// how many blocks of dirt lie between the grass and the stone
+const DIRT_DEPTH: usize = 4;
+/// surfaces up to this many blocks above the sea are beaches of sand
+const BEACH_HEIGHT: usize = 2;
+/// the size in blocks of the patches of forest and plains
+const BIOME_WAVELENGTH: f64 = 128.0;
+/// land where the biome noise lies above this is forest
+const FOREST_THRESHOLD: f64 = 0.1;
+
+pub struct TerrainGeneratorPlugin;
+
+impl Plugin for TerrainGeneratorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TerrainGenerator::new(rand::random()));
+    }
+}
+
+/// The kinds of land the world is made of, which are decorated differently.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+    /// land under the sea
+    Ocean,
+    /// sand right above the sea
+    Beach,
+    /// open grassland with the odd tree and plenty of flowers
+    Plains,
+    /// grassland covered in trees
+    Forest,
+}
+
+/// Generates the blocks of chunks from a heightmap made of layered noise, then decorates them
+/// with plants, see [`decorate`]. The same seed always produces the same world.
+#[derive(Resource, Clone)]
+pub struct TerrainGenerator {
+    seed: u64,
+    octaves: Vec<Perlin>,
+    /// picks between forest and plains
+    biomes: Perlin,
+}
+
+/// A freshly generated chunk, together with the blocks of its decorations that reach over its
+/// border into the chunks around it, by world position.
+pub struct GeneratedChunk {
+    pub chunk: Chunk,
+    pub overflow: Vec<(Position, BlockType)>,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let octaves = (0..OCTAVES).map(|_| Perlin::new(&mut rng)).collect();
+        Self {
+            seed,
+            octaves,
+            biomes: Perlin::new(&mut rng),
+        }
+    }
+
+    /// the seed the world is generated from
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// the number of solid blocks in the column at world `x` and `z`, so the surface block sits
+    /// at `height - 1`
+    pub fn height_at(&self, x: isize, z: isize) -> usize {
+        let mut noise = 0.0;
+        let mut amplitude = 1.0;
+        let mut wavelength = BASE_WAVELENGTH;
+        let mut total_amplitude = 0.0;
+        for octave in &self.octaves {
+            noise += octave.get(x as f64 / wavelength, z as f64 / wavelength) * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= 0.5;
+            wavelength *= 0.5;
+        }
+        // the summed noise rarely leaves -0.5..0.5, so it is stretched to about -1..1
+        let height = BASE_HEIGHT + noise / total_amplitude * 2.0 * HEIGHT_VARIATION;
+        (height.round() as usize).clamp(1, CHUNK_HEIGHT - 1)
+    }
+
+    /// the biome of the column at world `x` and `z`
+    pub fn biome_at(&self, x: isize, z: isize) -> Biome {
+        let height = self.height_at(x, z);
+        if height <= SEA_LEVEL {
+            Biome::Ocean
+        } else if height <= SEA_LEVEL + BEACH_HEIGHT {
+            Biome::Beach
+        } else if self.biomes.get(x as f64 / BIOME_WAVELENGTH, z as f64 / BIOME_WAVELENGTH) > FOREST_THRESHOLD {
+            Biome::Forest
+        } else {
+            Biome::Plains
+        }
+    }
+
+    /// generate the chunk at chunk coordinate `chunk_position` and decorate it
+    pub fn generate_chunk(&self, chunk_position: Position, registry: &BlockRegistry) -> GeneratedChunk {
+        let mut chunk = self.shape_chunk(chunk_position, registry);
+        let overflow = decorate(self, chunk_position, &mut chunk, registry);
+        GeneratedChunk { chunk, overflow }
+    }
+
+    /// fill the chunk at chunk coordinate `chunk_position` with terrain
+    fn shape_chunk(&self, chunk_position: Position, registry: &BlockRegistry) -> Chunk {
+        let blocks = TerrainBlocks::new(registry);
+        let origin = ChunkManager::chunk_origin(chunk_position);
+        let mut chunk = Chunk::new(origin.into(), BlockType::AIR);
+        for x in 0..CHUNK_WIDTH {
+            for z in 0..CHUNK_DEPTH {
+                let height = self.height_at(origin.x + x as isize, origin.z + z as isize);
+                let beach = height <= SEA_LEVEL + BEACH_HEIGHT;
+                for y in 0..height.max(SEA_LEVEL) {
+                    let block = if y >= height {
+                        blocks.water
+                    } else if y + DIRT_DEPTH < height {
+                        blocks.stone
+                    } else if beach {
+                        blocks.sand
+                    } else if y + 1 == height {
+                        blocks.grass
+                    } else {
+                        blocks.dirt
+                    };
+                    chunk.set(Position::new(x as isize, y as isize, z as isize), block);
+                }
+            }
+        }
+        chunk
+    }
+}
+
+/// The block types the terrain is built from.
+struct TerrainBlocks {
+    stone: BlockType,
+    dirt: BlockType,
+    grass: BlockType,
+    sand: BlockType,
+    water: BlockType,
+}
+
+impl TerrainBlocks {
+    /// # Panics
+    /// if one of the blocks is missing from the registry
+    fn new(registry: &BlockRegistry) -> Self {
+        let block = |name| {
+            registry
+                .by_name(name)
+                .unwrap_or_else(|| panic!("terrain generation needs a block called {name}"))
+        };
+        Self {
+            stone: block("stone"),
+            dirt: block("dirt"),
+            grass: block("grass"),
+            sand: block("sand"),
+            water: block("water"),
+        }
+    }
+}
+
+/// Two dimensional gradient noise, returning values roughly between -1 and 1 that change
+/// smoothly over a distance of about 1.
+#[derive(Clone)]
+struct Perlin {
+    /// a shuffled permutation of 0..256, repeated twice so lookups never need wrapping
+    permutation: Vec<u8>,
+}
+
+impl Perlin {
+    fn new(rng: &mut StdRng) -> Self {
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        permutation.shuffle(rng);
+        permutation.extend_from_within(..);
+        Self { permutation }
+    }
+
+    fn get(&self, x: f64, z: f64) -> f64 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (xi, zi) = ((x0 as i64 & 255) as usize, (z0 as i64 & 255) as usize);
+
+        let corner = |dx: usize, dz: usize| {
+            let hash = self.permutation[self.permutation[xi + dx] as usize + zi + dz];
+            Self::gradient(hash, fx - dx as f64, fz - dz as f64)
+        };
+        let (u, v) = (Self::fade(fx), Self::fade(fz));
+        let bottom = Self::lerp(corner(0, 0), corner(1, 0), u);
+        let top = Self::lerp(corner(0, 1), corner(1, 1), u);
+        Self::lerp(bottom, top, v)
+    }
+
+    /// the dot product of the offset with one of eight gradient directions picked by `hash`
+    fn gradient(hash: u8, x: f64, z: f64) -> f64 {
+        match hash & 7 {
+            0 => x + z,
+            1 => x - z,
+            2 => -x + z,
+            3 => -x - z,
+            4 => x,
+            5 => -x,
+            6 => z,
+            _ => -z,
+        }
+    }
+
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(a: f64, b: f64, t: f64) -> f64 {
+        a + (b - a) * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_seed_always_produces_the_same_heights() {
+        // changing these heights changes every world generated so far
+        let generator = TerrainGenerator::new(1234);
+        let columns = [(0, 0), (1, 0), (0, 1), (17, -5), (-40, 23), (100, 100), (-250, -180), (1000, 3)];
+        let heights = columns.map(|(x, z)| generator.height_at(x, z));
+        assert_eq!(heights, [68, 68, 68, 65, 55, 63, 68, 66]);
+    }
+
+    #[test]
+    fn generators_with_the_same_seed_produce_the_same_chunks() {
+        let registry = BlockRegistry::from_assets();
+        let (first, second) = (TerrainGenerator::new(1234), TerrainGenerator::new(1234));
+        for position in [Position::new(0, 0, 0), Position::new(-3, 0, 7)] {
+            let (a, b) = (first.generate_chunk(position, &registry), second.generate_chunk(position, &registry));
+            assert!(a.chunk == b.chunk, "chunk {position:?} differs");
+            assert_eq!(a.overflow, b.overflow);
+        }
+        // and a different seed produces a different world
+        let other = TerrainGenerator::new(4321).generate_chunk(Position::new(0, 0, 0), &registry);
+        assert!(other.chunk != first.generate_chunk(Position::new(0, 0, 0), &registry).chunk);
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::{app::AppExit, prelude::*, time::common_conditions::on_timer};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::block_types::{BlockRegistry, BlockType};
+use crate::chunk_manager::{Chunk, ChunkManager, Position};
+use crate::day_night::WorldTime;
+use crate::decoration::DeferredWrites;
+use crate::terrain_generator::TerrainGenerator;
+
+/// the directory the world is saved in, relative to the working directory
+const WORLD_DIRECTORY: &str = "saves/world";
+/// a region file holds the chunks of a square this many chunks wide
+const REGION_SIZE: isize = 32;
+/// the number of chunks in a region file
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE) as usize;
+/// the first bytes of every region file
+const REGION_MAGIC: [u8; 4] = *b"MCRG";
+const REGION_VERSION: u32 = 1;
+/// the size of the magic, the version and the table of chunk offsets and lengths
+const REGION_HEADER_LEN: usize = 8 + REGION_CHUNKS * 8;
+/// how often edited chunks are saved while playing
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Saves edited chunks to region files and loads them back instead of generating them again.
+/// Has to be added after the [`TerrainGeneratorPlugin`](crate::terrain_generator::TerrainGeneratorPlugin),
+/// so a saved world keeps its seed. The time of a saved world is picked up by the
+/// [`DayNightPlugin`](crate::day_night::DayNightPlugin) and its deferred decorations by the
+/// [`DecorationPlugin`](crate::decoration::DecorationPlugin) wherever they are added.
+pub struct WorldStoragePlugin;
+
+impl Plugin for WorldStoragePlugin {
+    fn build(&self, app: &mut App) {
+        let storage = WorldStorage::new(WORLD_DIRECTORY);
+        match storage.load_level() {
+            Ok(Some(level)) => {
+                app
+                    .insert_resource(TerrainGenerator::new(level.seed))
+                    .insert_resource(WorldTime::new(level.time));
+            }
+            Ok(None) => {}
+            Err(error) => error!("failed to read the level file, starting a new world: {error}"),
+        }
+        match storage.load_deferred_writes() {
+            Ok(writes) => {
+                app.insert_resource(writes);
+            }
+            Err(error) => error!("failed to read the deferred decorations, leaving them out: {error}"),
+        }
+        app
+            .insert_resource(storage)
+            .add_systems(Startup, save_level)
+            .add_systems(
+                Update,
+                (save_level, save_world, save_deferred_writes).chain().run_if(on_timer(AUTOSAVE_INTERVAL)),
+            )
+            // the decorations saved with their chunks are only left out of the saved decorations
+            // once the chunks are saved
+            .add_systems(Last, (save_level, save_world, save_deferred_writes).chain().run_if(on_event::<AppExit>()));
+    }
+}
+
+/// Everything about a world that isn't stored in its chunks.
+pub struct Level {
+    pub seed: u64,
+    /// the [`WorldTime`], in days since the world was created
+    pub time: f64,
+}
+
+/// The files a world is saved in.
+///
+/// Chunks are grouped into regions of [`REGION_SIZE`] by [`REGION_SIZE`] chunks, each saved in
+/// its own file under `region/`. A region file starts with a header holding the offset and
+/// length of every chunk in it, with a length of 0 for chunks that were never saved, followed
+/// by the zlib compressed chunks as written by [`Chunk::write_to`].
+#[derive(Resource, Clone)]
+pub struct WorldStorage {
+    directory: PathBuf,
+}
+
+impl WorldStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// the level of the world, or `None` if the world was never saved
+    pub fn load_level(&self) -> io::Result<Option<Level>> {
+        let text = match fs::read_to_string(self.level_path()) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut seed = None;
+        // worlds saved before there was a time of day carry on from the start of their first day
+        let mut time = WorldTime::default().days();
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "seed" => seed = Some(value.trim().parse().map_err(|_| invalid_data("the seed is not a number"))?),
+                "time" => time = value.trim().parse().map_err(|_| invalid_data("the time is not a number"))?,
+                _ => {}
+            }
+        }
+        let seed = seed.ok_or_else(|| invalid_data("the level has no seed"))?;
+        Ok(Some(Level { seed, time }))
+    }
+
+    pub fn save_level(&self, level: &Level) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        write_atomically(self.level_path(), format!("seed={}\ntime={}\n", level.seed, level.time).as_bytes())
+    }
+
+    /// The decorations waiting for their chunks to be saved with them, or none if the world was
+    /// never saved. Saved as one line of `x y z id` per block, and one line of `decorated x z`
+    /// for every chunk whose decorations were deferred.
+    pub fn load_deferred_writes(&self) -> io::Result<DeferredWrites> {
+        let text = match fs::read_to_string(self.deferred_writes_path()) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(DeferredWrites::default()),
+            Err(error) => return Err(error),
+        };
+        let coordinate = |value: &str| value.parse().map_err(|_| invalid_data(format!("{value:?} is not a coordinate")));
+        let mut writes = Vec::new();
+        let mut decorated = Vec::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["decorated", x, z] => decorated.push(Position::new(coordinate(x)?, 0, coordinate(z)?)),
+                [x, y, z, id] => {
+                    let id = id.parse().map_err(|_| invalid_data(format!("{id:?} is not a block id")))?;
+                    writes.push((Position::new(coordinate(x)?, coordinate(y)?, coordinate(z)?), BlockType::from_id(id)));
+                }
+                _ => return Err(invalid_data(format!("expected x y z id or decorated x z, found {line:?}"))),
+            }
+        }
+        Ok(DeferredWrites::new(writes, decorated))
+    }
+
+    pub fn save_deferred_writes(&self, writes: &DeferredWrites) -> io::Result<()> {
+        let mut text = String::new();
+        for (position, block) in writes.iter() {
+            text.push_str(&format!("{} {} {} {}\n", position.x, position.y, position.z, block.id()));
+        }
+        for chunk in writes.decorated() {
+            text.push_str(&format!("decorated {} {}\n", chunk.x, chunk.z));
+        }
+        fs::create_dir_all(&self.directory)?;
+        write_atomically(self.deferred_writes_path(), text.as_bytes())
+    }
+
+    /// The saved chunk at chunk coordinate `position`, or `None` if it was never saved. Chunks
+    /// holding blocks that are missing from `registry` are rejected.
+    pub fn load_chunk(&self, position: Position, registry: &BlockRegistry) -> io::Result<Option<Chunk>> {
+        let (region, slot) = Self::region_of(position);
+        let mut file = match File::open(self.region_path(region)) {
+            Ok(file) => BufReader::new(file),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let (offset, length) = read_region_header(&mut file)?[slot];
+        if length == 0 {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(offset as u64))?;
+        let mut decoder = ZlibDecoder::new(file.take(length as u64));
+        let origin = ChunkManager::chunk_origin(position);
+        let chunk = Chunk::read_from(origin.into(), &mut decoder)?;
+        if let Some(block) = chunk.palette().iter().find(|block| !registry.contains(**block)) {
+            return Err(invalid_data(format!("unknown block id {}", block.id())));
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Save the chunks, keyed by chunk coordinate, replacing older saves of them. Every region
+    /// file is rewritten as a whole and swapped in once it is complete, so chunks can be loaded
+    /// while others are saved.
+    pub fn save_chunks<'a>(&self, chunks: impl IntoIterator<Item = (Position, &'a Chunk)>) -> io::Result<()> {
+        let mut regions: HashMap<Position, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (position, chunk) in chunks {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            chunk.write_to(&mut encoder)?;
+            let (region, slot) = Self::region_of(position);
+            regions.entry(region).or_default().push((slot, encoder.finish()?));
+        }
+        if regions.is_empty() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(self.directory.join("region"))?;
+        for (region, chunks) in regions {
+            let path = self.region_path(region);
+            let mut slots = read_region(&path)?;
+            for (slot, data) in chunks {
+                slots[slot] = Some(data);
+            }
+            write_atomically(path, &encode_region(&slots))?;
+        }
+        Ok(())
+    }
+
+    /// the region containing the chunk at chunk coordinate `position`, and the chunk's slot in
+    /// that region's file
+    fn region_of(position: Position) -> (Position, usize) {
+        let region = Position::new(position.x.div_euclid(REGION_SIZE), 0, position.z.div_euclid(REGION_SIZE));
+        let slot = position.z.rem_euclid(REGION_SIZE) * REGION_SIZE + position.x.rem_euclid(REGION_SIZE);
+        (region, slot as usize)
+    }
+
+    fn region_path(&self, region: Position) -> PathBuf {
+        self.directory.join("region").join(format!("r.{}.{}.region", region.x, region.z))
+    }
+
+    fn level_path(&self) -> PathBuf {
+        self.directory.join("level.txt")
+    }
+
+    fn deferred_writes_path(&self) -> PathBuf {
+        self.directory.join("decorations.txt")
+    }
+}
+
+/// the offset and length of every chunk in a region file
+fn read_region_header(input: &mut impl Read) -> io::Result<Vec<(u32, u32)>> {
+    let mut header = vec![0; REGION_HEADER_LEN];
+    input.read_exact(&mut header)?;
+    if header[..4] != REGION_MAGIC {
+        return Err(invalid_data("not a region file"));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if version != REGION_VERSION {
+        return Err(invalid_data(format!("unsupported region file version {version}")));
+    }
+    Ok(header[8..]
+        .chunks_exact(8)
+        .map(|entry| {
+            (
+                u32::from_le_bytes(entry[..4].try_into().unwrap()),
+                u32::from_le_bytes(entry[4..].try_into().unwrap()),
+            )
+        })
+        .collect())
+}
+
+/// the compressed chunks in the region file at `path`, indexed by slot
+fn read_region(path: &Path) -> io::Result<Vec<Option<Vec<u8>>>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![None; REGION_CHUNKS]),
+        Err(error) => return Err(error),
+    };
+    read_region_header(&mut bytes.as_slice())?
+        .into_iter()
+        .map(|(offset, length)| {
+            if length == 0 {
+                return Ok(None);
+            }
+            let range = offset as usize..offset as usize + length as usize;
+            bytes
+                .get(range)
+                .map(|data| Some(data.to_vec()))
+                .ok_or_else(|| invalid_data("chunk out of region file bounds"))
+        })
+        .collect()
+}
+
+fn encode_region(slots: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(REGION_HEADER_LEN);
+    header.extend_from_slice(&REGION_MAGIC);
+    header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+    let mut chunks = Vec::new();
+    for slot in slots {
+        let (offset, length) = match slot {
+            Some(data) => {
+                let offset = REGION_HEADER_LEN + chunks.len();
+                chunks.extend_from_slice(data);
+                (offset as u32, data.len() as u32)
+            }
+            None => (0, 0),
+        };
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+    }
+    header.append(&mut chunks);
+    header
+}
+
+/// write `bytes` next to `path` first and then move them over it, so nothing ever sees a half
+/// written file
+fn write_atomically(path: PathBuf, bytes: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(temporary, path)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Save the seed and the time. The level is saved straight away too, so chunks saved before the
+/// first autosave match the terrain generated around them.
+fn save_level(storage: Res<WorldStorage>, generator: Res<TerrainGenerator>, time: Res<WorldTime>) {
+    if let Err(error) = storage.save_level(&Level { seed: generator.seed(), time: time.days() }) {
+        error!("failed to save the level: {error}");
+    }
+}
+
+/// save the decorations still waiting for their chunks to be saved with them
+fn save_deferred_writes(storage: Res<WorldStorage>, writes: Res<DeferredWrites>) {
+    if let Err(error) = storage.save_deferred_writes(&writes) {
+        error!("failed to save the deferred decorations: {error}");
+    }
+}
+
+/// save every chunk with unsaved edits
+pub fn save_world(storage: Res<WorldStorage>, mut chunks: ResMut<ChunkManager>, mut deferred: ResMut<DeferredWrites>) {
+    let dirty: Vec<Position> = chunks
+        .chunks
+        .iter()
+        .filter(|(_, chunk)| chunk.is_dirty())
+        .map(|(position, _)| *position)
+        .collect();
+    if dirty.is_empty() {
+        return;
+    }
+    if let Err(error) = storage.save_chunks(dirty.iter().map(|position| (*position, &chunks.chunks[position]))) {
+        error!("failed to save the world: {error}");
+        return;
+    }
+    for position in dirty {
+        if let Some(chunk) = chunks.chunks.get_mut(&position) {
+            chunk.mark_saved();
+        }
+        deferred.saved(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// storage in a directory of its own under the temporary directory, so tests running at the
+    /// same time don't share files
+    fn temporary_storage(name: &str) -> WorldStorage {
+        WorldStorage::new(std::env::temp_dir().join(format!("world-{name}-{}", std::process::id())))
+    }
+
+    /// the chunk at chunk coordinate `position`, filled with air except for `block` at `local`
+    fn chunk_with(position: Position, local: Position, block: BlockType) -> Chunk {
+        let mut chunk = Chunk::new(ChunkManager::chunk_origin(position).into(), BlockType::AIR);
+        chunk.set(local, block);
+        chunk
+    }
+
+    #[test]
+    fn chunks_share_region_files() {
+        let registry = BlockRegistry::from_assets();
+        let stone = registry.by_name("stone").unwrap();
+        let storage = temporary_storage("regions");
+        let (first, second) = (Position::new(0, 0, 0), Position::new(5, 0, 31));
+        let chunks = [
+            (first, chunk_with(first, Position::new(1, 2, 3), stone)),
+            (second, chunk_with(second, Position::new(4, 5, 6), stone)),
+        ];
+        assert!(storage.load_chunk(first, &registry).unwrap().is_none());
+        storage.save_chunks(chunks.iter().map(|(position, chunk)| (*position, chunk))).unwrap();
+
+        let bytes = fs::read(storage.region_path(Position::new(0, 0, 0))).unwrap();
+        assert_eq!(bytes[..4], REGION_MAGIC);
+        assert_eq!(bytes[4..8], REGION_VERSION.to_le_bytes());
+        let header = read_region_header(&mut bytes.as_slice()).unwrap();
+        let (_, first_slot) = WorldStorage::region_of(first);
+        let (_, second_slot) = WorldStorage::region_of(second);
+        assert_eq!(header[first_slot].0 as usize, REGION_HEADER_LEN);
+        assert_eq!(header[second_slot].0, header[first_slot].0 + header[first_slot].1);
+        assert_eq!(header.iter().filter(|(_, length)| *length > 0).count(), 2);
+
+        for (position, chunk) in &chunks {
+            assert_eq!(storage.load_chunk(*position, &registry).unwrap().as_ref(), Some(chunk));
+        }
+        // a chunk that was never saved into an existing region file
+        assert!(storage.load_chunk(Position::new(1, 0, 0), &registry).unwrap().is_none());
+        fs::remove_dir_all(&storage.directory).unwrap();
+    }
+
+    #[test]
+    fn saving_a_chunk_again_replaces_it() {
+        let registry = BlockRegistry::from_assets();
+        let stone = registry.by_name("stone").unwrap();
+        let dirt = registry.by_name("dirt").unwrap();
+        let storage = temporary_storage("overwrite");
+        let (edited, kept) = (Position::new(-1, 0, -33), Position::new(-2, 0, -33));
+        let kept_chunk = chunk_with(kept, Position::new(0, 0, 0), dirt);
+        storage
+            .save_chunks([(edited, &chunk_with(edited, Position::new(1, 2, 3), stone)), (kept, &kept_chunk)])
+            .unwrap();
+        let replacement = chunk_with(edited, Position::new(15, 200, 15), dirt);
+        storage.save_chunks([(edited, &replacement)]).unwrap();
+
+        // negative chunk coordinates round down to the region below them
+        assert!(storage.region_path(Position::new(-1, 0, -2)).exists());
+        assert_eq!(storage.load_chunk(edited, &registry).unwrap(), Some(replacement));
+        assert_eq!(storage.load_chunk(kept, &registry).unwrap(), Some(kept_chunk));
+        fs::remove_dir_all(&storage.directory).unwrap();
+    }
+
+    #[test]
+    fn deferred_writes_survive_saving_and_loading() {
+        let registry = BlockRegistry::from_assets();
+        let leaves = registry.by_name("leaves").unwrap();
+        let wood = registry.by_name("wood").unwrap();
+        let storage = temporary_storage("decorations");
+        assert_eq!(storage.load_deferred_writes().unwrap(), DeferredWrites::default());
+
+        let mut writes = DeferredWrites::new(
+            [(Position::new(17, 70, 3), leaves), (Position::new(-1, 64, -20), wood)],
+            [Position::new(0, 0, 0), Position::new(-1, 0, -2)],
+        );
+        // writes placed into a chunk that isn't saved yet are saved as well
+        writes.place_into(Position::new(1, 0, 0), &mut Chunk::new(Vec3::new(16.0, 0.0, 0.0), BlockType::AIR), &registry);
+        storage.save_deferred_writes(&writes).unwrap();
+        let loaded = storage.load_deferred_writes().unwrap();
+        fs::remove_dir_all(&storage.directory).unwrap();
+
+        let sorted = |writes: &DeferredWrites| {
+            let mut blocks: Vec<_> = writes.iter().map(|(position, block)| (position.x, position.y, position.z, block)).collect();
+            blocks.sort_by_key(|(x, y, z, _)| (*x, *y, *z));
+            let mut decorated: Vec<_> = writes.decorated().map(|chunk| (chunk.x, chunk.z)).collect();
+            decorated.sort();
+            (blocks, decorated)
+        };
+        assert_eq!(sorted(&loaded), sorted(&writes));
+    }
+}